@@ -16,4 +16,4 @@ img varchar(255),
 time varchar(64) not null,
 date varchar(64) not null,
 postid integer not null
-);
\ No newline at end of file
+);
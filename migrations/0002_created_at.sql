@@ -0,0 +1,34 @@
+-- replaces the varchar 'time' and 'date' columns with a single timestamptz.
+-- the old values were written as 'h:m:s' with the hour shifted by +2 (so possibly 24 or 25)
+-- and 'd.m.yyyy' in UTC. Rows that do not match this format fall back to the migration time.
+
+alter table threads add column created_at timestamptz;
+update threads set created_at = case
+    when date ~ '^\d{1,2}\.\d{1,2}\.\d{4}$' and time ~ '^\d{1,2}:\d{1,2}(:\d{1,2})?$' then
+        (to_date(date, 'DD.MM.YYYY') + make_interval(
+            hours => split_part(time, ':', 1)::int - 2,
+            mins => split_part(time, ':', 2)::int,
+            secs => coalesce(nullif(split_part(time, ':', 3), ''), '0')::double precision
+        )) at time zone 'UTC'
+    else now()
+end;
+alter table threads alter column created_at set not null;
+alter table threads alter column created_at set default now();
+alter table threads drop column time, drop column date;
+
+alter table posts add column created_at timestamptz;
+update posts set created_at = case
+    when date ~ '^\d{1,2}\.\d{1,2}\.\d{4}$' and time ~ '^\d{1,2}:\d{1,2}(:\d{1,2})?$' then
+        (to_date(date, 'DD.MM.YYYY') + make_interval(
+            hours => split_part(time, ':', 1)::int - 2,
+            mins => split_part(time, ':', 2)::int,
+            secs => coalesce(nullif(split_part(time, ':', 3), ''), '0')::double precision
+        )) at time zone 'UTC'
+    else now()
+end;
+alter table posts alter column created_at set not null;
+alter table posts alter column created_at set default now();
+alter table posts drop column time, drop column date;
+
+create index threads_created_at on threads (created_at);
+create index posts_created_at on posts (threadid, created_at);
@@ -0,0 +1,5 @@
+-- post numbers are allocated from a per-thread counter, incremented in the same transaction
+-- that inserts the post. Starts from the highest number already in use.
+
+alter table threads add column post_count integer not null default 0;
+update threads set post_count = coalesce((select max(postid) from posts where posts.threadid = threads.threadid), 0);
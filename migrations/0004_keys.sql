@@ -0,0 +1,28 @@
+-- thread ids are allocated by the DB from now on, and both tables get keys so that no id
+-- can be handed out twice.
+
+-- posts whose thread does not exist can never be shown
+delete from posts where not exists (select 1 from threads where threads.threadid = posts.threadid);
+
+-- the old file based counter could hand out the same number twice. Duplicates are moved to
+-- the end of their thread, the first post keeps its number.
+with extra as (
+    select ctid, threadid, row_number() over (partition by threadid order by postid, created_at) as n
+    from (
+        select ctid, threadid, postid, created_at,
+               row_number() over (partition by threadid, postid order by created_at) as copy
+        from posts
+    ) as copies
+    where copy > 1
+)
+update posts set postid = threads.post_count + extra.n
+from extra, threads
+where posts.ctid = extra.ctid and threads.threadid = extra.threadid;
+update threads set post_count = greatest(post_count, coalesce((select max(postid) from posts where posts.threadid = threads.threadid), 0));
+
+alter table threads add primary key (threadid);
+alter table posts add primary key (threadid, postid);
+alter table posts add foreign key (threadid) references threads (threadid) on delete cascade;
+
+-- candidates for new thread ids. Ids already taken by the old random scheme are skipped on insertion.
+create sequence threads_threadid_seq as integer owned by threads.threadid;
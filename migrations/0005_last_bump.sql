@@ -0,0 +1,9 @@
+-- threads are ordered by the time of their last bump, i.e. the last reply that was not saged
+-- and came before the bump limit. Existing threads count every reply as a bump.
+
+alter table threads add column last_bump timestamptz;
+update threads set last_bump = coalesce((select max(created_at) from posts where posts.threadid = threads.threadid), created_at);
+alter table threads alter column last_bump set not null;
+alter table threads alter column last_bump set default now();
+
+create index threads_last_bump on threads (last_bump desc);
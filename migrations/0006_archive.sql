@@ -0,0 +1,7 @@
+-- threads pushed off the index are archived instead of deleted. Archived threads are read-only,
+-- and are purged for good some time after they were archived.
+
+alter table threads add column archived_at timestamptz;
+
+create index threads_live on threads (last_bump desc) where archived_at is null;
+create index threads_archived_at on threads (archived_at) where archived_at is not null;
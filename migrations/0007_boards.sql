@@ -0,0 +1,22 @@
+-- threads are spread over several boards, each with its own limits. Existing threads are moved
+-- into the default board /b/. Thread ids stay unique over all boards.
+
+create table boards (
+    slug varchar(16) primary key,
+    title varchar(255) not null,
+    description text not null default '',
+    -- limits left empty fall back to the settings of the site
+    max_threads integer,
+    bump_limit integer,
+    nsfw boolean not null default false
+);
+
+insert into boards (slug, title) values ('b', 'Random');
+
+alter table threads add column board varchar(16) not null default 'b' references boards (slug);
+alter table threads alter column board drop default;
+
+-- the index and the archive are listed per board
+drop index threads_live;
+create index threads_live on threads (board, last_bump desc) where archived_at is null;
+create index threads_board_archived on threads (board, archived_at desc) where archived_at is not null;
@@ -0,0 +1,9 @@
+-- moderators can pin threads to the top of the index of their board, and lock threads against
+-- new replies.
+
+alter table threads add column sticky boolean not null default false;
+alter table threads add column locked boolean not null default false;
+
+-- stickies come first on the index
+drop index threads_live;
+create index threads_live on threads (board, sticky desc, last_bump desc) where archived_at is null;
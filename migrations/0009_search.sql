@@ -0,0 +1,10 @@
+-- full-text search over threads and posts. The vectors are generated by postgres itself whenever
+-- a row changes, which needs PostgreSQL 12 or newer.
+
+alter table threads add column search tsvector generated always as
+    (setweight(to_tsvector('english', title), 'A') || setweight(to_tsvector('english', body), 'B')) stored;
+alter table posts add column search tsvector generated always as
+    (to_tsvector('english', body)) stored;
+
+create index threads_search on threads using gin (search);
+create index posts_search on posts using gin (search);
@@ -0,0 +1,17 @@
+-- which posts quote which, for the backlinks shown under each post. 'quoted' is the postid of a
+-- post in the same thread, 0 for the opening post.
+
+create table post_references (
+    threadid integer not null,
+    postid integer not null,
+    quoted integer not null,
+    primary key (threadid, quoted, postid),
+    foreign key (threadid, postid) references posts (threadid, postid) on delete cascade
+);
+
+-- existing posts are only scanned roughly, new ones are parsed like their bodies are rendered
+-- (see src/markup.rs), which e.g. leaves out quotes in code blocks.
+insert into post_references (threadid, postid, quoted)
+select distinct posts.threadid, posts.postid, quote[1]::integer
+from posts, regexp_matches(posts.body, '(?<!>)>>([0-9]{1,9})', 'g') as quote
+where quote[1]::integer < posts.postid;
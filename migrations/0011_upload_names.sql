@@ -0,0 +1,12 @@
+-- uploads are stored under the hash of their content (see src/upload.rs), the name the file was
+-- uploaded as is kept next to it for display. Earlier uploads were stored under that name.
+
+alter table threads add column img_name text not null default '';
+alter table posts add column img_name text not null default '';
+
+update threads set img_name = regexp_replace(img, '^Pictures/', '') where img like 'Pictures/%';
+update posts set img_name = regexp_replace(img, '^Pictures/', '') where img like 'Pictures/%';
+
+-- the same file can now belong to several posts, it is only deleted once none is left
+create index threads_img on threads (img);
+create index posts_img on posts (img);
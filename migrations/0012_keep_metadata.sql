@@ -0,0 +1,4 @@
+-- uploads lose their EXIF, XMP and other metadata before they are stored (see src/metadata.rs),
+-- unless their board is set to keep it.
+
+alter table boards add column keep_metadata boolean not null default false;
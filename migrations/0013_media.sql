@@ -0,0 +1,49 @@
+-- attachments get a table of their own, referenced by the threads and posts that have one.
+-- Threads and posts without an image used to store the text 'error parsing this image' in 'img',
+-- they now have no media at all.
+
+create table media (
+    id serial primary key,
+    -- SHA-256 of the stored file, empty for files uploaded under their own name (see 'oboe rename-uploads')
+    hash varchar(64) not null,
+    mime varchar(32) not null,
+    -- 0 if unknown, for images uploaded before this table existed
+    width integer not null,
+    height integer not null,
+    byte_size bigint not null,
+    original_name text not null default '',
+    path text not null,
+    thumbnail text not null
+);
+
+-- a file is deleted once no row points to it anymore
+create index media_path on media (path);
+
+alter table threads add column media_id integer references media (id);
+alter table posts add column media_id integer references media (id);
+create index threads_media on threads (media_id);
+create index posts_media on posts (media_id);
+
+-- one row for every file and name it was uploaded with. The thumbnail is named like in src/thumbnail.rs.
+insert into media (hash, mime, width, height, byte_size, original_name, path, thumbnail)
+select coalesce(substring(img from '^Pictures/([0-9a-f]{64})\.[a-z]+$'), ''),
+       case lower(substring(img from '\.([A-Za-z]+)$'))
+           when 'png' then 'image/png'
+           when 'gif' then 'image/gif'
+           when 'webp' then 'image/webp'
+           else 'image/jpeg'
+       end,
+       0, 0, 0, img_name, img,
+       case
+           when img ~ '\.webp$' then regexp_replace(img, '\.webp$', '.thumb.png')
+           when img ~ '^Pictures/[^/]*\.[^./]*$' then regexp_replace(img, '\.([^./]*)$', '.thumb.\1')
+           else img || '.thumb'
+       end
+from (select img, img_name from threads union select img, img_name from posts) as images
+where img like 'Pictures/%';
+
+update threads set media_id = media.id from media where media.path = threads.img and media.original_name = threads.img_name;
+update posts set media_id = media.id from media where media.path = posts.img and media.original_name = posts.img_name;
+
+alter table threads drop column img, drop column img_name;
+alter table posts drop column img, drop column img_name;
@@ -0,0 +1,28 @@
+-- threads and posts can have several images. Instead of being referenced by them, every row of
+-- 'media' now belongs to one opening post or post, in the order the images were attached.
+
+alter table media add column threadid integer references threads (threadid) on delete cascade;
+-- 0 for the opening post, like in 'post_references'
+alter table media add column postid integer;
+alter table media add column position integer not null default 0;
+
+-- migration 13 gave images uploaded twice under the same name one row, every post gets its own copy
+insert into media (hash, mime, width, height, byte_size, original_name, path, thumbnail, threadid, postid)
+select media.hash, media.mime, media.width, media.height, media.byte_size, media.original_name, media.path, media.thumbnail,
+       threads.threadid, 0
+from threads join media on media.id = threads.media_id;
+insert into media (hash, mime, width, height, byte_size, original_name, path, thumbnail, threadid, postid)
+select media.hash, media.mime, media.width, media.height, media.byte_size, media.original_name, media.path, media.thumbnail,
+       posts.threadid, posts.postid
+from posts join media on media.id = posts.media_id;
+
+alter table threads drop column media_id;
+alter table posts drop column media_id;
+delete from media where threadid is null;
+
+alter table media alter column threadid set not null, alter column postid set not null, alter column position drop default;
+create index media_post on media (threadid, postid, position);
+
+-- 'None' uses the 'max_thread_attachments' and 'max_reply_attachments' settings
+alter table boards add column max_thread_attachments integer;
+alter table boards add column max_reply_attachments integer;
@@ -0,0 +1,2 @@
+-- attachments marked as spoilers show a placeholder instead of their thumbnail until clicked
+alter table media add column spoiler boolean not null default false;
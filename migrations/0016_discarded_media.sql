@@ -0,0 +1,8 @@
+-- the files of images whose thread was purged, until they are deleted from the media store.
+-- Purging records them in the same transaction that deletes the threads, so a file that could not
+-- be deleted is tried again by the next purge instead of being forgotten.
+
+create table discarded_media (
+    path text primary key,
+    thumbnail text not null
+);
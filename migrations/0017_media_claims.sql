@@ -0,0 +1,11 @@
+-- images that are being attached to a post which is not added yet, see 'Storage::claim_image'.
+-- Identical uploads share their files, which are not deleted while an image is claimed, even if no
+-- row of 'media' refers to it yet. Adding the post turns its claims into rows of 'media'.
+
+create table media_claims (
+    id serial primary key,
+    path text not null,
+    claimed_at timestamptz not null default now()
+);
+
+create index media_claims_path on media_claims (path);
@@ -1,27 +1,53 @@
-use serde_json::Result;
-use crate::postgres::*;
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::markup;
+use crate::media::MediaUrls;
+use crate::postgres::{Media, Post};
+use crate::storage::Storage;
+use crate::xmlify;
 
 // very simple api that returns JSON data if requested. Currently only used for AJAX
 // to fetch new comments.
 
-pub fn api_posts_after(threadid: &i32, after_postid: &i32) -> Result<(String)> {
-    let posts = match retrieve_posts(threadid) {
-        Some(posts) => {
-            posts
-        },
-        _ => {
-            return Ok("nil".to_owned());
-        }
-    };
-    let mut json_buffer = String::from("[");
+// a 'Post' as sent to clients, with its body rendered to HTML the same way the pages show it
+#[derive(Serialize)]
+struct RenderedPost<'a> {
+    #[serde(flatten)]
+    post: &'a Post,
+    attachments: Vec<RenderedMedia<'a>>,
+    body_html: String,
+    // the posts quoting this one, see 'Backlinks'
+    replies: &'a [i32]
+}
+
+// an attached image with where clients load it and its thumbnail from, see 'MediaUrls'. Clients
+// show the placeholder for spoilers themselves, until they are clicked.
+#[derive(Serialize)]
+struct RenderedMedia<'a> {
+    #[serde(flatten)]
+    media: &'a Media,
+    url: String,
+    thumbnail_url: String
+}
 
-    for post in posts.iter() {
-        if post.postid > *after_postid {
-            &json_buffer.push_str(serde_json::to_string(post)?.as_str());
-            &json_buffer.push(',');
-        }
-    }
-    &json_buffer.pop();
-    &json_buffer.push(']');
-    Ok(json_buffer)
-} 
\ No newline at end of file
+// all posts of a thread with a 'postid' greater than 'after_postid', as a JSON array.
+// The array is empty if there are none, but the thread itself has to exist on 'board'.
+pub fn api_posts_after(storage: &dyn Storage, board: &str, threadid: &i32, after_postid: &i32, urls: MediaUrls) -> Result<String> {
+    let op = storage.retrieve_op_on(board, threadid)?;
+    let thread = xmlify::clickable_thread(&op);
+    let posts = storage.retrieve_posts(threadid)?.unwrap_or_default();
+    let backlinks = storage.retrieve_backlinks(threadid)?;
+    let new_posts: Vec<RenderedPost> = posts.iter()
+        .filter(|post| post.postid > *after_postid)
+        .map(|post| RenderedPost {
+            post,
+            attachments: post.attachments.iter()
+                .map(|media| RenderedMedia { media, url: urls.of(&media.path), thumbnail_url: urls.of(&media.thumbnail) })
+                .collect(),
+            body_html: markup::render(&post.body, &thread).into_string(),
+            replies: backlinks.of(&post.postid)
+        })
+        .collect();
+    Ok(serde_json::to_string(&new_posts)?)
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::io::ErrorKind;
+
+use chrono::Utc;
+
+use crate::config::Settings;
+use crate::error::Result;
+use crate::media::MediaStore;
+use crate::postgres::{Board, Thread};
+use crate::storage::Storage;
+use crate::upload;
+
+// Keeps 'board' at its limit of live threads. Run after every new thread: the least recently
+// bumped threads are archived, and threads of any board archived longer than 'purge_after' are
+// deleted. Their images are recorded as discarded by the storage and deleted from 'media' afterwards,
+// so files a failed prune left behind are deleted by the next one.
+pub fn prune(storage: &dyn Storage, media: &dyn MediaStore, board: &Board, settings: &Settings) -> Result<()> {
+    let max_threads = board.max_threads.map(|n| n as i64).unwrap_or(settings.max_threads);
+    storage.archive_overflow(&board.slug, max_threads)?;
+    if let Some(purge_after) = settings.purge_after {
+        for thread in storage.purge_archived(Utc::now() - purge_after)? {
+            remove_metainfo(&thread)?;
+        }
+    }
+    for (path, thumbnail) in storage.discarded_media()? {
+        upload::discard(&path, &thumbnail, storage, media)?;
+        storage.forget_discarded(&path)?;
+    }
+    Ok(())
+}
+
+// deletes the file of a purged thread in the old 'metainfo/' directory if it is still around
+fn remove_metainfo(thread: &Thread) -> Result<()> {
+    match fs::remove_file(format!("metainfo/{}", thread.op.threadid)) {
+        Ok(()) => Ok(()),
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::RwLock;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use chrono::{Duration, Utc};
+
+    use crate::archive::prune;
+    use crate::config::Settings;
+    use crate::error::{OboeError, Result};
+    use crate::media::MediaStore;
+    use crate::memory::MemoryStorage;
+    use crate::postgres::{Media, OP};
+    use crate::storage::Storage;
+
+    // keeps files in memory, deleting them fails like an unreachable store while 'unreachable' is set
+    #[derive(Default)]
+    struct FlakyStore {
+        files: RwLock<HashMap<String, Vec<u8>>>,
+        unreachable: AtomicBool
+    }
+
+    impl MediaStore for FlakyStore {
+        fn put(&self, name: &str, content: &[u8], _mime: &str) -> Result<()> {
+            self.files.write().unwrap().insert(name.to_owned(), content.to_vec());
+            Ok(())
+        }
+
+        fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.files.read().unwrap().get(name).cloned())
+        }
+
+        fn exists(&self, name: &str) -> Result<bool> {
+            Ok(self.files.read().unwrap().contains_key(name))
+        }
+
+        fn delete(&self, name: &str) -> Result<()> {
+            if self.unreachable.load(Ordering::SeqCst) {
+                return Err(OboeError::Io(io::Error::new(io::ErrorKind::Other, "unreachable")));
+            }
+            self.files.write().unwrap().remove(name);
+            Ok(())
+        }
+    }
+
+    fn thread_with_image(name: &str) -> OP {
+        let media = Media {
+            id: 0,
+            hash: String::new(),
+            mime: "image/png".to_owned(),
+            width: 1,
+            height: 1,
+            byte_size: 1,
+            original_name: name.to_owned(),
+            path: format!("Pictures/{}.png", name),
+            thumbnail: format!("Pictures/{}.thumb.png", name),
+            spoiler: false
+        };
+        OP {
+            threadid: 0,
+            board: "b".to_owned(),
+            poster: "Anonymous".to_owned(),
+            title: name.to_owned(),
+            body: "first!".to_owned(),
+            attachments: vec![media],
+            created_at: Utc::now(),
+            last_bump: Utc::now(),
+            post_count: 0,
+            archived_at: None,
+            sticky: false,
+            locked: false
+        }
+    }
+
+    #[test]
+    fn failed_deletes_are_retried() {
+        // every thread is archived, and purged, as soon as it is pruned
+        let settings = Settings { max_threads: 0, purge_after: Some(Duration::seconds(-1)), ..Settings::default() };
+        let storage = MemoryStorage::new(&settings);
+        let board = storage.retrieve_board("b").unwrap();
+        let media = FlakyStore::default();
+        for name in &["old.png", "old.thumb.png"] {
+            media.put(name, b"content", "image/png").unwrap();
+        }
+
+        storage.add_thread(&thread_with_image("old")).unwrap();
+        media.unreachable.store(true, Ordering::SeqCst);
+        assert!(prune(&storage, &media, &board, &settings).is_err());
+        assert_eq!(storage.count_threads("b", true).unwrap(), 0);
+        assert!(media.exists("old.png").unwrap());
+
+        media.unreachable.store(false, Ordering::SeqCst);
+        prune(&storage, &media, &board, &settings).unwrap();
+        assert!(!media.exists("old.png").unwrap());
+        assert!(!media.exists("old.thumb.png").unwrap());
+        assert!(storage.discarded_media().unwrap().is_empty());
+    }
+}
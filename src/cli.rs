@@ -0,0 +1,325 @@
+use std::{fs, io, process};
+use std::path::Path;
+use std::slice;
+
+use regex::Regex;
+
+use crate::config::Settings;
+use crate::error::OboeError;
+use crate::media::{self, MediaStore};
+use crate::postgres::{Board, PostgresStorage, get_psql_entry};
+use crate::storage::Storage;
+use crate::thumbnail;
+use crate::upload;
+
+// Maintenance commands, run as 'oboe <command> [arguments]' instead of starting the server.
+pub fn run(command: &str, arguments: &[String], settings: &Settings) {
+    match command {
+        "migrate" => migrate(settings),
+        "import-metainfo" => import_metainfo(settings),
+        "thumbnails" => thumbnails(settings),
+        "rename-uploads" => rename_uploads(settings),
+        "add-board" => add_board(arguments, settings),
+        "metadata" => metadata(arguments, settings),
+        "attachments" => attachments(arguments, settings),
+        "sticky" => moderate(arguments, settings, |storage, threadid| storage.set_sticky(threadid, true)),
+        "unsticky" => moderate(arguments, settings, |storage, threadid| storage.set_sticky(threadid, false)),
+        "lock" => moderate(arguments, settings, |storage, threadid| storage.set_locked(threadid, true)),
+        "unlock" => moderate(arguments, settings, |storage, threadid| storage.set_locked(threadid, false)),
+        other => {
+            println!("Unknown command \"{}\". Available commands: migrate, import-metainfo, thumbnails, rename-uploads, add-board, metadata, attachments, sticky, unsticky, lock, unlock", other);
+            process::exit(1);
+        },
+    }
+}
+
+// brings the DB at 'SQL_URL' up to the schema version this binary expects
+fn migrate(settings: &Settings) {
+    let storage = PostgresStorage::new(&get_psql_entry(), settings);
+    match storage.migrate() {
+        Ok(applied) => {
+            if applied.is_empty() {
+                println!("The DB schema is already up to date.");
+            }
+            for migration in applied {
+                println!("Applied migration {:04} ({}).", migration.version, migration.name);
+            }
+        },
+        Err(err) => {
+            println!("Migration failed, the DB was left at the last successful migration. \n Detailed Error: \n\n {}", err);
+            process::exit(1);
+        },
+    }
+}
+
+// One-time import of the post counters that used to be kept in 'metainfo/<threadid>' files
+// containing 'posts=<n>'. Counters only ever go up, so running this twice does no harm.
+fn import_metainfo(settings: &Settings) {
+    let storage = PostgresStorage::new(&get_psql_entry(), settings);
+    let entries = fs::read_dir("metainfo").unwrap_or_else(|err| {
+        println!("Could not read \"metainfo/\". \n Detailed Error: \n\n {}", err);
+        process::exit(1);
+    });
+
+    let mut imported = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let threadid = match entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) {
+            Some(threadid) => threadid,
+            // e.g. the placeholder keeping the directory in git
+            None => continue,
+        };
+        let count = match fs::read_to_string(entry.path()).ok().and_then(|content| parse_metainfo(&content)) {
+            Some(count) => count,
+            None => {
+                println!("Skipping metainfo/{}, it does not contain a post counter.", threadid);
+                continue;
+            },
+        };
+        match storage.seed_post_count(&threadid, &count) {
+            Ok(true) => imported += 1,
+            Ok(false) => println!("Skipping metainfo/{}, the thread does not exist.", threadid),
+            Err(err) => {
+                println!("Import failed at metainfo/{}. \n Detailed Error: \n\n {}", threadid, err);
+                process::exit(1);
+            },
+        }
+    }
+    println!("Imported the post counters of {} threads. \"metainfo/\" is no longer used and may be deleted.", imported);
+}
+
+// Makes the thumbnails of the images in 'Pictures/' that were uploaded before thumbnails existed.
+// Images that already have one are left alone, so this can be run again after it was interrupted.
+// Only looks at local files, uploads kept in object storage got theirs when they were uploaded.
+fn thumbnails(settings: &Settings) {
+    let entries = fs::read_dir("Pictures").unwrap_or_else(|err| {
+        println!("Could not read \"Pictures/\". \n Detailed Error: \n\n {}", err);
+        process::exit(1);
+    });
+
+    let mut generated = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = format!("Pictures/{}", entry.file_name().to_string_lossy());
+        let extension = Path::new(&path).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+        if !thumbnail::EXTENSIONS.contains(&extension) || thumbnail::is_thumbnail(&path) {
+            continue;
+        }
+        if Path::new(&thumbnail::thumbnail_path(&path)).exists() {
+            continue;
+        }
+        match thumbnail::generate(&path, settings.thumbnail_width, settings.thumbnail_height) {
+            Ok(_) => generated += 1,
+            Err(err) => println!("Skipping {}, no thumbnail could be made. \n Detailed Error: \n\n {}", path, err),
+        }
+    }
+    println!("Made {} thumbnails.", generated);
+}
+
+// One-time move of the images uploaded before they were named after their content (see upload.rs)
+// to their new names, together with the threads and posts showing them. The old files are only
+// deleted once the DB points to the new ones, so this can be run again after it was interrupted.
+// Their metadata is stripped like that of new uploads, whatever their board is set to. The new files
+// go to the media store of the settings, so this also moves old uploads into object storage.
+fn rename_uploads(settings: &Settings) {
+    let storage = PostgresStorage::new(&get_psql_entry(), settings);
+    let media = media::from_settings(settings);
+    let entries = fs::read_dir(upload::DIRECTORY).unwrap_or_else(|err| {
+        println!("Could not read \"{}/\". \n Detailed Error: \n\n {}", upload::DIRECTORY, err);
+        process::exit(1);
+    });
+
+    let mut renamed = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let old = format!("{}/{}", upload::DIRECTORY, name);
+        // only images were ever served, the rest is e.g. 'NOTICE.md'
+        let extension = Path::new(&name).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+        if !thumbnail::EXTENSIONS.contains(&extension) || upload::is_stored_name(&name) || thumbnail::is_thumbnail(&old) {
+            continue;
+        }
+        match rename_upload(&storage, &*media, &old, settings) {
+            Ok(new) => {
+                println!("Moved {} to {}.", old, new);
+                renamed += 1;
+            },
+            Err(err) => println!("Skipping {}. \n Detailed Error: \n\n {}", old, err),
+        }
+    }
+    println!("Moved {} images.", renamed);
+}
+
+fn rename_upload(storage: &PostgresStorage, media: &dyn MediaStore, old: &str, settings: &Settings) -> Result<String, OboeError> {
+    // the old files were served as they were uploaded, they lose their metadata now
+    let new = upload::store(Path::new(old), String::new(), false, storage, media, settings)?;
+    // the new files are claimed until the rows point to them
+    if let Err(err) = storage.rename_image(old, &new) {
+        upload::abandon(slice::from_ref(&new), storage, media)?;
+        return Err(err);
+    }
+    storage.release_image(&new.path)?;
+    for path in &[old.to_owned(), thumbnail::thumbnail_path(old)] {
+        match fs::remove_file(path) {
+            Ok(()) => {},
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(new.path)
+}
+
+// 'oboe add-board <slug> <title> [description=<text>] [max_threads=<n>] [bump_limit=<n>] [thread_attachments=<n>] [reply_attachments=<n>] [nsfw] [keep_metadata]'
+fn add_board(arguments: &[String], settings: &Settings) {
+    let board = parse_board(arguments).unwrap_or_else(|err| {
+        println!("{} \n Usage: oboe add-board <slug> <title> [description=<text>] [max_threads=<n>] [bump_limit=<n>] [thread_attachments=<n>] [reply_attachments=<n>] [nsfw] [keep_metadata]", err);
+        process::exit(1);
+    });
+    let storage = PostgresStorage::new(&get_psql_entry(), settings);
+    match storage.add_board(&board) {
+        Ok(()) => println!("Added the board /{}/.", board.slug),
+        Err(err) => {
+            println!("Could not add the board. \n Detailed Error: \n\n {}", err);
+            process::exit(1);
+        },
+    }
+}
+
+// reads a 'Board' out of the arguments of 'add-board'. Limits that are not given fall back to the settings.
+fn parse_board(arguments: &[String]) -> Result<Board, String> {
+    let (slug, title) = match arguments {
+        [slug, title, ..] => (slug.to_owned(), title.to_owned()),
+        _ => return Err("A board needs at least a slug and a title.".to_owned()),
+    };
+    let mut board = Board {
+        slug,
+        title,
+        description: String::new(),
+        max_threads: None,
+        bump_limit: None,
+        nsfw: false,
+        keep_metadata: false,
+        max_thread_attachments: None,
+        max_reply_attachments: None
+    };
+    for option in arguments[2..].iter() {
+        let number = |value: &str| value.parse::<i32>().map_err(|_| format!("\"{}\" is not a number.", value));
+        match option.splitn(2, '=').collect::<Vec<&str>>().as_slice() {
+            ["description", value] => board.description = (*value).to_owned(),
+            ["max_threads", value] => board.max_threads = Some(number(*value)?),
+            ["bump_limit", value] => board.bump_limit = Some(number(*value)?),
+            ["thread_attachments", value] => board.max_thread_attachments = Some(number(*value)?),
+            ["reply_attachments", value] => board.max_reply_attachments = Some(number(*value)?),
+            ["nsfw"] => board.nsfw = true,
+            ["keep_metadata"] => board.keep_metadata = true,
+            _ => return Err(format!("Unknown option \"{}\".", option)),
+        }
+    }
+    Ok(board)
+}
+
+// 'oboe metadata <slug> <strip|keep>', whether images uploaded to the board from now on keep
+// their metadata
+fn metadata(arguments: &[String], settings: &Settings) {
+    let (slug, keep_metadata) = match arguments {
+        [slug, choice] if choice == "strip" => (slug, false),
+        [slug, choice] if choice == "keep" => (slug, true),
+        _ => {
+            println!("Usage: oboe metadata <slug> <strip|keep>");
+            process::exit(1);
+        },
+    };
+    let storage = PostgresStorage::new(&get_psql_entry(), settings);
+    match storage.set_keep_metadata(slug, keep_metadata) {
+        Ok(()) => println!("Images uploaded to /{}/ now {} their metadata.", slug, if keep_metadata { "keep" } else { "lose" }),
+        Err(err) => {
+            println!("Could not update the board /{}/. \n Detailed Error: \n\n {}", slug, err);
+            process::exit(1);
+        },
+    }
+}
+
+// 'oboe attachments <slug> <thread> <reply>', how many images new threads and replies on the board
+// can have. Either is a number, or 'default' for the setting.
+fn attachments(arguments: &[String], settings: &Settings) {
+    let limit = |value: &String| if value == "default" { Ok(None) } else { value.parse::<i32>().map(Some) };
+    let (slug, thread, reply) = match arguments {
+        [slug, thread, reply] => match (limit(thread), limit(reply)) {
+            (Ok(thread), Ok(reply)) => (slug, thread, reply),
+            _ => {
+                println!("The limits have to be numbers or \"default\". \n Usage: oboe attachments <slug> <thread> <reply>");
+                process::exit(1);
+            },
+        },
+        _ => {
+            println!("Usage: oboe attachments <slug> <thread> <reply>");
+            process::exit(1);
+        },
+    };
+    let storage = PostgresStorage::new(&get_psql_entry(), settings);
+    match storage.set_max_attachments(slug, thread, reply) {
+        Ok(()) => println!("Updated how many images threads and replies on /{}/ can have.", slug),
+        Err(err) => {
+            println!("Could not update the board /{}/. \n Detailed Error: \n\n {}", slug, err);
+            process::exit(1);
+        },
+    }
+}
+
+// 'oboe <sticky|unsticky|lock|unlock> <threadid>', applies 'action' to the thread
+fn moderate<F>(arguments: &[String], settings: &Settings, action: F) where F: Fn(&dyn Storage, &i32) -> Result<(), OboeError> {
+    let threadid = match arguments.first().and_then(|argument| argument.parse::<i32>().ok()) {
+        Some(threadid) => threadid,
+        None => {
+            println!("Give the id of the thread, e.g. \"oboe lock 17\".");
+            process::exit(1);
+        },
+    };
+    let storage = PostgresStorage::new(&get_psql_entry(), settings);
+    match action(&storage, &threadid) {
+        Ok(()) => println!("Updated thread {}.", threadid),
+        Err(err) => {
+            println!("Could not update thread {}. \n Detailed Error: \n\n {}", threadid, err);
+            process::exit(1);
+        },
+    }
+}
+
+// reads the counter out of the content of a metainfo file
+fn parse_metainfo(content: &str) -> Option<i32> {
+    // compiles the regex only the first time, then uses cached version
+    lazy_static!{
+        static ref CACHED_REGEX: Regex = Regex::new(r"posts=(-?\d+)").unwrap();
+    }
+    CACHED_REGEX.captures(content).and_then(|group| group[1].parse::<i32>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{parse_board, parse_metainfo};
+
+    #[test]
+    fn metainfo_counters() {
+        assert_eq!(parse_metainfo("posts=0"), Some(0));
+        assert_eq!(parse_metainfo("posts=17"), Some(17));
+        assert_eq!(parse_metainfo("posts=9\n"), Some(9));
+        assert_eq!(parse_metainfo("posts="), None);
+        assert_eq!(parse_metainfo(""), None);
+    }
+
+    #[test]
+    fn board_arguments() {
+        let arguments = |list: &[&str]| list.iter().map(|argument| argument.to_string()).collect::<Vec<String>>();
+        let board = parse_board(&arguments(&["g", "Technology", "description=all things computers", "bump_limit=500", "reply_attachments=0", "nsfw"])).unwrap();
+        assert_eq!(board.slug, "g");
+        assert_eq!(board.title, "Technology");
+        assert_eq!(board.description, "all things computers");
+        assert_eq!(board.max_threads, None);
+        assert_eq!(board.bump_limit, Some(500));
+        assert!(board.nsfw);
+        assert!(!board.keep_metadata);
+        assert_eq!(board.max_thread_attachments, None);
+        assert_eq!(board.max_reply_attachments, Some(0));
+
+        assert!(parse_board(&arguments(&["g"])).is_err());
+        assert!(parse_board(&arguments(&["g", "Technology", "max_threads=many"])).is_err());
+        assert!(parse_board(&arguments(&["g", "Technology", "sfw"])).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use rocket::Config;
+
+// Settings read once at startup from the rocket config. Every key can be set in 'Rocket.toml'
+// or through an environment variable of the form 'ROCKET_<KEY>'.
+pub struct Settings {
+    // which 'Storage' backend to use, either "postgres" or "memory"
+    pub storage: String,
+    // which 'MediaStore' uploaded images are kept in, either "local" for 'Pictures/' or "s3"
+    pub media_store: String,
+    // the S3-compatible object storage used by "s3", e.g. "http://localhost:9000" for MinIO.
+    // Objects are addressed path-style, '<endpoint>/<bucket>/<name>'.
+    pub s3_endpoint: String,
+    pub s3_bucket: String,
+    pub s3_region: String,
+    pub s3_access_key: String,
+    pub s3_secret_key: String,
+    // pages link images under this URL instead of '/Pictures/', e.g. a CDN in front of the bucket
+    pub media_url: Option<String>,
+    // maximum number of open connections to the DB
+    pub pool_size: u32,
+    // how long a request waits for a free connection before giving up
+    pub pool_timeout: Duration,
+    // hand out thread ids that can't be guessed instead of counting up
+    pub random_thread_ids: bool,
+    // replies after this many no longer bump their thread
+    pub bump_limit: i32,
+    // threads on each page of the index
+    pub threads_per_page: u32,
+    // images on each page of the gallery
+    pub images_per_page: u32,
+    // number of replies in the shortened view of long threads
+    pub last_replies: u32,
+    // matches on each page of the search results
+    pub results_per_page: u32,
+    // thumbnails are scaled down to fit into this many pixels
+    pub thumbnail_width: u32,
+    pub thumbnail_height: u32,
+    // uploads bigger than this many bytes are refused
+    pub max_upload_bytes: u64,
+    // images wider or higher than this many pixels are refused
+    pub max_image_width: u32,
+    pub max_image_height: u32,
+    // and those with more pixels than this in total, which would take too much memory to decode
+    pub max_image_pixels: u64,
+    // images a new thread and a reply can have, for boards without limits of their own
+    pub max_thread_attachments: i32,
+    pub max_reply_attachments: i32,
+    // live threads on the board, beyond that the least recently bumped ones are archived
+    pub max_threads: i64,
+    // how long archived threads are kept before they are deleted, 'None' keeps them forever
+    pub purge_after: Option<chrono::Duration>
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            storage: "postgres".to_owned(),
+            media_store: "local".to_owned(),
+            s3_endpoint: "http://localhost:9000".to_owned(),
+            s3_bucket: "oboe".to_owned(),
+            s3_region: "us-east-1".to_owned(),
+            s3_access_key: String::new(),
+            s3_secret_key: String::new(),
+            media_url: None,
+            pool_size: 10,
+            pool_timeout: Duration::from_secs(5),
+            random_thread_ids: false,
+            bump_limit: 300,
+            threads_per_page: 15,
+            images_per_page: 60,
+            last_replies: 50,
+            results_per_page: 20,
+            thumbnail_width: 250,
+            thumbnail_height: 250,
+            max_upload_bytes: 8 * 1024 * 1024,
+            max_image_width: 10000,
+            max_image_height: 10000,
+            max_image_pixels: 50_000_000,
+            max_thread_attachments: 4,
+            max_reply_attachments: 4,
+            max_threads: 150,
+            purge_after: Some(chrono::Duration::days(7))
+        }
+    }
+}
+
+impl Settings {
+    pub fn from_config(config: &Config) -> Settings {
+        let default = Settings::default();
+        Settings {
+            storage: config.get_str("storage").map(|s| s.to_owned()).unwrap_or(default.storage),
+            media_store: config.get_str("media_store").map(|s| s.to_owned()).unwrap_or(default.media_store),
+            s3_endpoint: config.get_str("s3_endpoint").map(|s| s.to_owned()).unwrap_or(default.s3_endpoint),
+            s3_bucket: config.get_str("s3_bucket").map(|s| s.to_owned()).unwrap_or(default.s3_bucket),
+            s3_region: config.get_str("s3_region").map(|s| s.to_owned()).unwrap_or(default.s3_region),
+            s3_access_key: config.get_str("s3_access_key").map(|s| s.to_owned()).unwrap_or(default.s3_access_key),
+            s3_secret_key: config.get_str("s3_secret_key").map(|s| s.to_owned()).unwrap_or(default.s3_secret_key),
+            // an empty URL serves images from '/Pictures/' as well
+            media_url: config.get_str("media_url").ok().filter(|url| !url.is_empty()).map(|url| url.to_owned()),
+            pool_size: config.get_int("db_pool_size").map(|n| n as u32).unwrap_or(default.pool_size),
+            pool_timeout: config.get_int("db_pool_timeout").map(|s| Duration::from_secs(s as u64)).unwrap_or(default.pool_timeout),
+            random_thread_ids: config.get_bool("random_thread_ids").unwrap_or(default.random_thread_ids),
+            bump_limit: config.get_int("bump_limit").map(|n| n as i32).unwrap_or(default.bump_limit),
+            threads_per_page: config.get_int("threads_per_page").map(|n| n as u32).unwrap_or(default.threads_per_page),
+            images_per_page: config.get_int("images_per_page").map(|n| n as u32).unwrap_or(default.images_per_page),
+            last_replies: config.get_int("last_replies").map(|n| n as u32).unwrap_or(default.last_replies),
+            results_per_page: config.get_int("results_per_page").map(|n| n as u32).unwrap_or(default.results_per_page),
+            thumbnail_width: config.get_int("thumbnail_width").map(|n| n as u32).unwrap_or(default.thumbnail_width),
+            thumbnail_height: config.get_int("thumbnail_height").map(|n| n as u32).unwrap_or(default.thumbnail_height),
+            max_upload_bytes: config.get_int("max_upload_bytes").map(|n| n as u64).unwrap_or(default.max_upload_bytes),
+            max_image_width: config.get_int("max_image_width").map(|n| n as u32).unwrap_or(default.max_image_width),
+            max_image_height: config.get_int("max_image_height").map(|n| n as u32).unwrap_or(default.max_image_height),
+            max_image_pixels: config.get_int("max_image_pixels").map(|n| n as u64).unwrap_or(default.max_image_pixels),
+            max_thread_attachments: config.get_int("max_thread_attachments").map(|n| n as i32).unwrap_or(default.max_thread_attachments),
+            max_reply_attachments: config.get_int("max_reply_attachments").map(|n| n as i32).unwrap_or(default.max_reply_attachments),
+            max_threads: config.get_int("max_threads").unwrap_or(default.max_threads),
+            // set to 0 to keep archived threads forever
+            purge_after: match config.get_int("purge_after_hours") {
+                Ok(0) => None,
+                Ok(hours) => Some(chrono::Duration::hours(hours)),
+                Err(_) => default.purge_after,
+            }
+        }
+    }
+}
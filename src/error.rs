@@ -0,0 +1,176 @@
+use std::{error, fmt, io};
+use std::io::Cursor;
+
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket_multipart_form_data::MultipartFormDataError;
+use serde::Serialize;
+
+use crate::xmlify;
+
+// Everything that can go wrong while handling a request. Handlers return it as the error half of
+// their 'Result', and rocket turns it into a response with a fitting status code.
+#[derive(Debug)]
+pub enum OboeError {
+    // the DB rejected a query or could not be reached
+    Database(postgres::Error),
+    // no pooled connection became free in time
+    Pool(r2d2::Error),
+    Io(io::Error),
+    Json(serde_json::Error),
+    // a page could not be rendered from its template
+    Template(askama::Error),
+    // the submitted form is incomplete or malformed
+    Validation(String),
+    // the requested thread, post or file does not exist
+    NotFound(String),
+    // the request is understood, but not allowed, e.g. replying to an archived thread
+    Forbidden(String),
+    // the uploaded file can't be accepted
+    Upload(String),
+    // the uploaded file is bigger than allowed
+    TooLarge(String)
+}
+
+pub type Result<T> = std::result::Result<T, OboeError>;
+
+impl OboeError {
+    pub fn status(&self) -> Status {
+        match self {
+            OboeError::Validation(_) | OboeError::Upload(_) => Status::BadRequest,
+            OboeError::NotFound(_) => Status::NotFound,
+            OboeError::Forbidden(_) => Status::Forbidden,
+            OboeError::TooLarge(_) => Status::PayloadTooLarge,
+            OboeError::Database(_) | OboeError::Pool(_) | OboeError::Io(_) | OboeError::Json(_)
+            | OboeError::Template(_) => Status::InternalServerError,
+        }
+    }
+
+    // the message shown to the user. Internal errors are only described in the log, since
+    // they may contain details about the DB or file system.
+    pub fn public_message(&self) -> String {
+        match self {
+            OboeError::Validation(message) | OboeError::NotFound(message) | OboeError::Forbidden(message)
+            | OboeError::Upload(message) | OboeError::TooLarge(message) => message.clone(),
+            _ => "Internal Server Error. If you are an admin, look at rocket's logs.".to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for OboeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OboeError::Database(err) => write!(f, "database error: {}", err),
+            OboeError::Pool(err) => write!(f, "connection pool error: {}", err),
+            OboeError::Io(err) => write!(f, "io error: {}", err),
+            OboeError::Json(err) => write!(f, "json error: {}", err),
+            OboeError::Template(err) => write!(f, "template error: {}", err),
+            OboeError::Validation(message) => write!(f, "invalid form: {}", message),
+            OboeError::NotFound(message) => write!(f, "not found: {}", message),
+            OboeError::Forbidden(message) => write!(f, "forbidden: {}", message),
+            OboeError::Upload(message) => write!(f, "invalid upload: {}", message),
+            OboeError::TooLarge(message) => write!(f, "upload too large: {}", message),
+        }
+    }
+}
+
+impl error::Error for OboeError {}
+
+impl From<postgres::Error> for OboeError {
+    fn from(err: postgres::Error) -> OboeError {
+        OboeError::Database(err)
+    }
+}
+
+impl From<r2d2::Error> for OboeError {
+    fn from(err: r2d2::Error) -> OboeError {
+        OboeError::Pool(err)
+    }
+}
+
+impl From<io::Error> for OboeError {
+    fn from(err: io::Error) -> OboeError {
+        OboeError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for OboeError {
+    fn from(err: serde_json::Error) -> OboeError {
+        OboeError::Json(err)
+    }
+}
+
+impl From<askama::Error> for OboeError {
+    fn from(err: askama::Error) -> OboeError {
+        OboeError::Template(err)
+    }
+}
+
+impl From<MultipartFormDataError> for OboeError {
+    fn from(err: MultipartFormDataError) -> OboeError {
+        match err {
+            MultipartFormDataError::DataTooLargeError(field) => OboeError::TooLarge(format!("The field \"{}\" is too large.", field)),
+            MultipartFormDataError::DataTypeError(field) => OboeError::Upload(format!("The field \"{}\" has a type that is not accepted.", field)),
+            MultipartFormDataError::IOError(err) => OboeError::Io(err),
+            _ => OboeError::Validation("The submitted form could not be read.".to_owned()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    status: u16,
+    error: String
+}
+
+// clients asking for JSON (like main.js) get '{"status": .., "error": ..}', everyone else an error page
+impl<'r> Responder<'r> for OboeError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let status = self.status();
+        if status.code >= 500 {
+            println!("Error while handling {}: {}", request.uri(), self);
+        }
+
+        let wants_json = request.accept()
+            .map(|accept| accept.preferred().is_json())
+            .unwrap_or(false);
+        let (content_type, body) = if wants_json {
+            let body = ErrorBody { status: status.code, error: self.public_message() };
+            (ContentType::JSON, serde_json::to_string(&body).map_err(|_| Status::InternalServerError)?)
+        } else {
+            (ContentType::HTML, xmlify::xmlify_for_error(status, &self.public_message()).map_err(|_| Status::InternalServerError)?)
+        };
+
+        Response::build()
+            .status(status)
+            .header(content_type)
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+
+    use crate::error::OboeError;
+
+    #[test]
+    fn statuses() {
+        assert_eq!(OboeError::Validation("".to_owned()).status(), Status::BadRequest);
+        assert_eq!(OboeError::Upload("".to_owned()).status(), Status::BadRequest);
+        assert_eq!(OboeError::NotFound("".to_owned()).status(), Status::NotFound);
+        assert_eq!(OboeError::Forbidden("".to_owned()).status(), Status::Forbidden);
+        assert_eq!(OboeError::TooLarge("".to_owned()).status(), Status::PayloadTooLarge);
+        let io = std::io::Error::new(std::io::ErrorKind::Other, "disk on fire");
+        assert_eq!(OboeError::from(io).status(), Status::InternalServerError);
+    }
+
+    #[test]
+    fn internal_details_stay_private() {
+        let io = std::io::Error::new(std::io::ErrorKind::Other, "disk on fire");
+        assert!(!OboeError::from(io).public_message().contains("disk on fire"));
+        assert_eq!(OboeError::NotFound("no such thread".to_owned()).public_message(), "no such thread");
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+// HTML built in rust that is safe to send as it is, templates show it with '|safe'. There are only
+// two ways to get a 'Markup': escaping text with 'Markup::text', or declaring HTML as trusted with
+// 'Markup::raw'. Everything users submit must go through the first, 'raw' is meant for the tags
+// written here in the source.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Markup(String);
+
+impl Markup {
+    // 'text' shown as it is, whatever characters it contains
+    pub fn text(text: &str) -> Markup {
+        Markup(escape(text))
+    }
+
+    // 'html' taken over unchanged. Never pass anything that was submitted by a user.
+    pub fn raw<S: Into<String>>(html: S) -> Markup {
+        Markup(html.into())
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl fmt::Display for Markup {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
+// replaces the characters that would otherwise be read as markup, in text as well as in quoted attributes
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// percent-encodes 'text' to be used as a value in the query of a link, or as a segment of a path.
+// Only letters, digits and '-', '_', '.', '~' are kept as they are.
+pub fn percent_encode(text: &str) -> String {
+    text.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html::{Markup, escape, percent_encode};
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape("<script>alert('x')</script>"), "&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;");
+        assert_eq!(escape("\" onload=\"x"), "&quot; onload=&quot;x");
+        assert_eq!(escape("fish & chips"), "fish &amp; chips");
+        assert_eq!(escape("plain"), "plain");
+    }
+
+    #[test]
+    fn markup_from_text_is_escaped() {
+        assert_eq!(Markup::text("<b>bold</b>").to_string(), "&lt;b&gt;bold&lt;/b&gt;");
+        assert_eq!(Markup::raw("<b>bold</b>").to_string(), "<b>bold</b>");
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(percent_encode("cat.thumb.png"), "cat.thumb.png");
+        assert_eq!(percent_encode("a b&c=d/é"), "a%20b%26c%3Dd%2F%C3%A9");
+    }
+}
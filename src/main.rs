@@ -12,112 +12,174 @@ extern crate regex;
 mod postgres;
 // defines tools needed for parsing multipart POST requests
 mod multipart;
+// HTML built outside of templates, escaped unless marked as trusted
+mod html;
+// greentext, quote links and the other formatting of post bodies
+mod markup;
 // XML (de)serializer of content recieved from the DBMS
 mod xmlify;
-// writing metadata to disk
-mod metainfo;
 // accessing data in JSON form through a api
 mod api;
+// interface every backend holding threads and posts implements
+mod storage;
+// storage backend that lives in memory only
+mod memory;
+// settings read from the rocket config at startup
+mod config;
+// versioned schema of the DB
+mod migrations;
+// maintenance commands such as 'oboe migrate'
+mod cli;
+// errors a request can end in, and how they are shown to the client
+mod error;
+// moving old threads out of the way
+mod archive;
+// scaled down copies of uploaded images
+mod thumbnail;
+// where uploaded images are kept, named after their content
+mod upload;
+// where uploaded files are kept, on disk or in object storage, and the URLs they are shown with
+mod media;
+// object storage speaking the S3 API, such as MinIO
+mod s3;
+// removes EXIF and other metadata from uploaded images
+mod metadata;
 
-use std::io;
-use std::path::{PathBuf, Path};
+use std::{env, io};
+use std::path::Path;
 
 use rocket::response::NamedFile;
 use rocket::Data;
-use rocket::http::ContentType;
+use rocket::http::{ContentType, Status};
 use rocket::response::Redirect;
-use rocket::response::content::Html;
+use rocket::response::content::{Content, Html, Json};
+use rocket::{Rocket, State};
+
+use crate::config::Settings;
+use crate::error::OboeError;
+use crate::media::{MediaStore, MediaUrls};
+use crate::storage::{Storage, find_board, thread_not_found};
 
 // GET requests
 
-#[get("/makethread")]
-fn makethread() -> io::Result<NamedFile> {
-    NamedFile::open("static/makethread.html")
+// the titlepage lists every board
+#[get("/")]
+fn getboards(storage: State<Box<dyn Storage>>) -> Result<Html<String>, OboeError> {
+    Ok(Html(xmlify::xmlify_for_boards(&storage.retrieve_boards()?)?))
 }
 
-#[get("/")]
-fn getindex() -> Html<String> {
-    Html(xmlify::xmlify_for_index(postgres::retrieve_all_op()))
+#[get("/<board>/makethread")]
+fn makethread(board: String, storage: State<Box<dyn Storage>>, settings: State<Settings>) -> Result<Html<String>, OboeError> {
+    let boards = storage.retrieve_boards()?;
+    let board = find_board(&boards, &board)?;
+    Ok(Html(xmlify::xmlify_for_makethread(board, &boards, board.max_attachments(false, &settings))?))
 }
 
-#[get("/gallery")]
-fn getgallery() -> Html<String> {
-    Html(xmlify::xmlify_for_gallery(postgres::retrieve_all_op(), postgres::retrieve_all_posts()))
+// the index and gallery are paginated with '?page=N', starting at 1
+#[get("/<board>?<page>")]
+fn getindex(board: String, page: Option<u32>, storage: State<Box<dyn Storage>>, settings: State<Settings>) -> Result<Html<String>, OboeError> {
+    let boards = storage.retrieve_boards()?;
+    let board = find_board(&boards, &board)?;
+    let openings = storage.retrieve_index_page(&board.slug, false, page.unwrap_or(1), settings.threads_per_page)?;
+    Ok(Html(xmlify::xmlify_for_index(board, &boards, openings, MediaUrls::new(&settings))?))
 }
 
-#[get("/thread/<threadid>")]
-fn getindvidualthread(threadid: i32) -> Result<Html<String>, Redirect> {
-    match postgres::retrieve_thread(&threadid) {
-        Ok(thread) => {
-            return Ok(
-                Html(
-                    xmlify::xmlify_for_indvthread(thread)
-                )
-            );
-        },
-        Err(_err) => {
-            return Err(
-                Redirect::to("/404")
-            );
-        },
+// threads pushed off the index, they stay reachable under '/<board>/thread/<threadid>' until purged
+#[get("/<board>/archive?<page>")]
+fn getarchive(board: String, page: Option<u32>, storage: State<Box<dyn Storage>>, settings: State<Settings>) -> Result<Html<String>, OboeError> {
+    let boards = storage.retrieve_boards()?;
+    let board = find_board(&boards, &board)?;
+    let openings = storage.retrieve_index_page(&board.slug, true, page.unwrap_or(1), settings.threads_per_page)?;
+    Ok(Html(xmlify::xmlify_for_archive(board, &boards, openings)?))
+}
+
+#[get("/<board>/gallery?<page>")]
+fn getgallery(board: String, page: Option<u32>, storage: State<Box<dyn Storage>>, settings: State<Settings>) -> Result<Html<String>, OboeError> {
+    let boards = storage.retrieve_boards()?;
+    let board = find_board(&boards, &board)?;
+    let images = storage.retrieve_gallery_page(&board.slug, page.unwrap_or(1), settings.images_per_page)?;
+    Ok(Html(xmlify::xmlify_for_gallery(board, &boards, images, MediaUrls::new(&settings))?))
+}
+
+// '?last=N' only shows the last N comments of the thread
+#[get("/<board>/thread/<threadid>?<last>")]
+fn getindvidualthread(board: String, threadid: i32, last: Option<u32>, storage: State<Box<dyn Storage>>, settings: State<Settings>) -> Result<Html<String>, OboeError> {
+    let boards = storage.retrieve_boards()?;
+    let board = find_board(&boards, &board)?;
+    let thread = match last {
+        Some(count) => storage.retrieve_thread_tail(&threadid, count as i64)?,
+        None => storage.retrieve_thread(&threadid)?,
+    };
+    if thread.op.board != board.slug {
+        return Err(thread_not_found());
     }
+    let backlinks = storage.retrieve_backlinks(&threadid)?;
+    Ok(Html(xmlify::xmlify_for_indvthread(board, &boards, thread, backlinks, settings.last_replies, board.max_attachments(true, &settings), MediaUrls::new(&settings))?))
+}
+
+// full-text search over every board, see 'Storage::search'. Without '?q=' only the form is shown.
+#[get("/search?<q>&<page>")]
+fn getsearch(q: Option<String>, page: Option<u32>, storage: State<Box<dyn Storage>>, settings: State<Settings>) -> Result<Html<String>, OboeError> {
+    let boards = storage.retrieve_boards()?;
+    let query = q.unwrap_or_default();
+    let results = if query.trim().is_empty() {
+        None
+    } else {
+        Some(storage.retrieve_search_page(&query, page.unwrap_or(1), settings.results_per_page)?)
+    };
+    Ok(Html(xmlify::xmlify_for_search(&query, results, &boards)?))
+}
+
+// links from before there were boards keep working. Ranked below the routes of the boards,
+// which have the same shape.
+#[get("/thread/<threadid>", rank = 2)]
+fn getoldthread(threadid: i32, storage: State<Box<dyn Storage>>) -> Result<Redirect, OboeError> {
+    let op = storage.retrieve_op(&threadid)?.ok_or_else(thread_not_found)?;
+    Ok(Redirect::permanent(xmlify::clickable_thread(&op)))
 }
 
 // GET API requests
 
 // gets all new posts after a certain post, if the thread exists
-#[get("/thread/<threadid>/<after_postid>")]
-fn api_new_posts(threadid: i32, after_postid: i32) -> Result<String, Redirect> {
-    match api::api_posts_after(&threadid, &after_postid) {
-        Ok(json) => {
-            Ok(
-                json
-            )
-        },
-        Err(_err) => {
-            return Err(
-                Redirect::to("/500")
-            )
-        },
-    }
+#[get("/<board>/thread/<threadid>/<after_postid>")]
+fn api_new_posts(board: String, threadid: i32, after_postid: i32, storage: State<Box<dyn Storage>>, settings: State<Settings>) -> Result<Json<String>, OboeError> {
+    Ok(Json(api::api_posts_after(&**storage, &board, &threadid, &after_postid, MediaUrls::new(&settings))?))
 }
 
 // POST requests
 
-// creation of a thread
-#[post("/makethread",data = "<data>")]
-fn makethread_post(content_type: &ContentType, data: Data) -> Redirect {
-    match multipart::eval_multipart_thread(content_type, data) {
-        None => {
-            return Redirect::to("/form");
-        },
-        Some(thread) => {
-            match thread.add_thread() {
-                Ok(threadid) => {
-                    return Redirect::to(format!("/thread/{}", threadid))
-                },
-                Err(_) => {
-                    return Redirect::to("/500")
-                },
-            }
+// creation of a thread, which may push old threads of the board into the archive
+#[post("/<board>/makethread",data = "<data>")]
+fn makethread_post(board: String, content_type: &ContentType, data: Data, storage: State<Box<dyn Storage>>, media: State<Box<dyn MediaStore>>, settings: State<Settings>) -> Result<Redirect, OboeError> {
+    let board = storage.retrieve_board(&board)?;
+    let thread = multipart::eval_multipart_thread(&board, content_type, data, &**storage, &**media, &settings)?;
+    let threadid = match storage.add_thread(&thread) {
+        Ok(threadid) => threadid,
+        Err(err) => {
+            // the images stored for it belong to no thread
+            upload::abandon(&thread.attachments, &**storage, &**media)?;
+            return Err(err);
         },
+    };
+    // the thread is added either way, what a failed prune leaves behind is done by the next one
+    if let Err(err) = archive::prune(&**storage, &**media, &board, &settings) {
+        println!("Error while pruning /{}/: {}", board.slug, err);
     }
+    Ok(Redirect::to(format!("/{}/thread/{}", board.slug, threadid)))
 }
 
-// creation of a post on a specif   ic thread
-#[post("/thread/<threadid>", data = "<data>")]
-fn threadid_post(threadid: i32, content_type: &ContentType, data: Data) -> Redirect {
-    match multipart::eval_multipart_post(threadid, content_type, data) {
-        None => {
-            return Redirect::to("/form");
-        },
-        Some(post) => {
-            if post.add_post().is_err() {
-                return Redirect::to("/500");
-            }
-        }
+// creation of a post on a specific thread, unless it is locked or archived
+#[post("/<board>/thread/<threadid>", data = "<data>")]
+fn threadid_post(board: String, threadid: i32, content_type: &ContentType, data: Data, storage: State<Box<dyn Storage>>, media: State<Box<dyn MediaStore>>, settings: State<Settings>) -> Result<Redirect, OboeError> {
+    let board = storage.retrieve_board(&board)?;
+    let op = storage.retrieve_op_on(&board.slug, &threadid)?;
+    let post = multipart::eval_multipart_post(&board, &op, content_type, data, &**storage, &**media, &settings)?;
+    if let Err(err) = storage.add_post(&post) {
+        // e.g. the thread was locked meanwhile, its images belong to no post
+        upload::abandon(&post.attachments, &**storage, &**media)?;
+        return Err(err);
     }
-    Redirect::to(format!("/thread/{}", threadid))
+    Ok(Redirect::to(xmlify::clickable_thread(&op)))
 }
 
 // Static links to content
@@ -142,57 +204,644 @@ fn logo() -> io::Result<NamedFile> {
     NamedFile::open("static/oboe.png")
 }
 
+// shown instead of the thumbnails of spoiler images, see 'xmlify::thumbnail_url'
+#[get("/static/spoiler.svg")]
+fn spoiler_placeholder() -> io::Result<NamedFile> {
+    NamedFile::open("static/spoiler.svg")
+}
+
 #[get("/static/main.js")]
 fn get_js() -> io::Result<NamedFile> {
     NamedFile::open("static/main.js")
 }
 
 #[get("/404")]
-fn fourofour() -> io::Result<NamedFile> {
-    NamedFile::open("static/404.html")
+fn fourofour() -> Result<Html<String>, OboeError> {
+    Ok(Html(xmlify::xmlify_for_error(Status::NotFound, "The specified URL does not exist.")?))
 }
 
 #[get("/500")]
-fn fivehundred() -> io::Result<NamedFile> {
-    NamedFile::open("static/500.html")
+fn fivehundred() -> Result<Html<String>, OboeError> {
+    Ok(Html(xmlify::xmlify_for_error(Status::InternalServerError, "Internal Server Error. If you are an admin, look at rocket's logs.")?))
 }
 
 #[get("/form")]
-fn form_err() -> io::Result<NamedFile> {
-    NamedFile::open("static/form.html")
+fn form_err() -> Result<Html<String>, OboeError> {
+    Ok(Html(xmlify::xmlify_for_invalid_form()?))
 }
 
 
-// let unauthorized users get all files in Pictures/ , iff they are either png or jpg/jpeg
-#[get("/Pictures/<file..>")]
-fn pictures(file: PathBuf) -> Option<NamedFile> {
-    match file.extension() {
-        Some(fileending) if (!(fileending == "png" || fileending == "jpeg" || fileending == "jpg" || fileending == "gif")) => return None,
-        _ => {}
+// let unauthorized users get the uploaded images and their thumbnails, by the names they are stored
+// under, from whichever media store keeps them. Nothing else in the store is served.
+// With 'media_url' set, pages link to that instead, but older links keep working.
+// '/Pictures/...' also matches routes of a board like '/<board>/makethread', so it is ranked
+// before every default rank, which go down to -6.
+#[get("/Pictures/<file>", rank = -7)]
+fn pictures(file: String, media: State<Box<dyn MediaStore>>) -> Result<Option<Content<Vec<u8>>>, OboeError> {
+    if !upload::is_stored_name(&file) {
+        return Ok(None);
     }
-    NamedFile::open(Path::new("Pictures/").join(file)).ok()
+    let content_type = Path::new(&file).extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary);
+    Ok(media.get(&file)?.map(|content| Content(content_type, content)))
+}
+
+// requests that do not match any route, or fail outside of a handler, get the same error
+// responses as the ones returned by handlers
+#[catch(404)]
+fn not_found() -> OboeError {
+    OboeError::NotFound("The specified URL does not exist.".to_owned())
+}
+
+#[catch(500)]
+fn internal_error() -> OboeError {
+    OboeError::Io(io::Error::new(io::ErrorKind::Other, "request failed outside of a handler"))
 }
 
-// launchable
 // defines all routes that should be made available
+fn mount(rocket: Rocket) -> Rocket {
+    rocket.mount("/", routes![form_err,
+        fivehundred,
+        fourofour,
+        pictures,
+        logo,
+        spoiler_placeholder,
+        get_js,
+        makethread,
+        getboards,
+        getindvidualthread,
+        getoldthread,
+        getsearch,
+        getgallery,
+        getindex,
+        getarchive,
+        api_new_posts,
+        threadid_post,
+        makethread_post,
+        get_css,
+        inter_regular,
+        inter_bold]
+    ).register(catchers![not_found, internal_error])
+}
+
+// launchable
+// the storage backend and the media store are picked from the rocket config, see storage.rs, media.rs and config.rs
+// if a command is given, e.g. 'oboe migrate', it is run instead of the server
 fn main() {
-    let rocket = rocket::ignite()
-        .mount("/", routes![form_err,
-            fivehundred,
-            fourofour,
-            pictures,
-            logo,
-            get_js,
-            makethread,
-            getindvidualthread,
-            getgallery,
-            getindex,
-            api_new_posts,
-            threadid_post,
-            makethread_post,
-            get_css,
-            inter_regular,
-            inter_bold]
-        );
-    rocket.launch();
+    let rocket = rocket::ignite();
+    let settings = Settings::from_config(rocket.config());
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some((command, arguments)) = args.split_first() {
+        cli::run(command, arguments, &settings);
+        return;
+    }
+    let storage = storage::from_settings(&settings);
+    let media = media::from_settings(&settings);
+    mount(rocket.manage(storage).manage(media).manage(settings)).launch();
+}
+
+// every route is exercised in-process against the in-memory backend, no database needed
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+    use rocket::http::{Accept, ContentType, Header, Status};
+    use rocket::local::Client;
+
+    use crate::config::Settings;
+    use crate::error::Result;
+    use crate::media::{LocalStore, MediaStore};
+    use crate::memory::MemoryStorage;
+    use crate::postgres::Board;
+    use crate::storage::Storage;
+
+    const BOUNDARY: &str = "oboeTestBoundary";
+
+    fn client() -> Client {
+        client_with(Settings::default())
+    }
+
+    fn client_with(settings: Settings) -> Client {
+        client_with_storage(MemoryStorage::new(&settings), settings)
+    }
+
+    fn client_with_storage(storage: MemoryStorage, settings: Settings) -> Client {
+        let storage: Box<dyn Storage> = Box::new(storage);
+        let media: Box<dyn MediaStore> = Box::new(TemporaryStore::new());
+        Client::new(super::mount(rocket::ignite().manage(storage).manage(media).manage(settings))).expect("valid rocket instance")
+    }
+
+    // Uploads of the tests are kept in a directory of each client under the temp dir, never in the
+    // 'Pictures/' of the checkout. It is deleted together with the client.
+    struct TemporaryStore {
+        directory: PathBuf,
+        store: LocalStore
+    }
+
+    impl TemporaryStore {
+        fn new() -> TemporaryStore {
+            static CLIENTS: AtomicUsize = AtomicUsize::new(0);
+            let directory = env::temp_dir().join(format!("oboe-test-media-{}-{}", process::id(), CLIENTS.fetch_add(1, Ordering::SeqCst)));
+            fs::create_dir_all(&directory).unwrap();
+            let store = LocalStore::new(&directory.to_string_lossy());
+            TemporaryStore { directory, store }
+        }
+    }
+
+    impl MediaStore for TemporaryStore {
+        fn put(&self, name: &str, content: &[u8], mime: &str) -> Result<()> {
+            self.store.put(name, content, mime)
+        }
+
+        fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+            self.store.get(name)
+        }
+
+        fn exists(&self, name: &str) -> Result<bool> {
+            self.store.exists(name)
+        }
+
+        fn delete(&self, name: &str) -> Result<()> {
+            self.store.delete(name)
+        }
+    }
+
+    impl Drop for TemporaryStore {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    // the media store managed by the client
+    fn media(client: &Client) -> &dyn MediaStore {
+        &**client.rocket().state::<Box<dyn MediaStore>>().expect("managed media store")
+    }
+
+    // the storage managed by the client, for changes that have no route
+    fn storage(client: &Client) -> &dyn Storage {
+        &**client.rocket().state::<Box<dyn Storage>>().expect("managed storage")
+    }
+
+    // builds a multipart/form-data body out of text fields only
+    fn multipart_body(fields: &[(&str, &str)]) -> String {
+        multipart_body_with_files(fields, &[])
+    }
+
+    // the same with a file in the "image" field for each of 'files', by name. Their content is not
+    // an image, so only forms refused before the images are read can be tested with it.
+    fn multipart_body_with_files(fields: &[(&str, &str)], files: &[&str]) -> String {
+        let mut body = String::new();
+        for (name, value) in fields {
+            body.push_str(&format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value));
+        }
+        for file in files {
+            body.push_str(&format!("--{}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"{}\"\r\nContent-Type: image/png\r\n\r\nnot an image\r\n",
+                                   BOUNDARY, file));
+        }
+        body.push_str(&format!("--{}--\r\n", BOUNDARY));
+        body
+    }
+
+    // the same with images, each with the name it is uploaded as. An empty name and content is a
+    // file input in which nothing was chosen.
+    fn multipart_body_with_images(fields: &[(&str, &str)], images: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value).into_bytes());
+        }
+        for (file, content) in images {
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"{}\"\r\nContent-Type: image/png\r\n\r\n",
+                                BOUNDARY, file).into_bytes());
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", BOUNDARY).into_bytes());
+        body
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut content = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height)).write_to(&mut content, ImageOutputFormat::Png).unwrap();
+        content.into_inner()
+    }
+
+    fn multipart_header() -> Header<'static> {
+        Header::new("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY))
+    }
+
+    // posts a new thread on /b/ and returns the location the client is redirected to
+    fn make_thread(client: &Client, title: &str, body: &str) -> String {
+        make_thread_on(client, "b", title, body)
+    }
+
+    fn make_thread_on(client: &Client, board: &str, title: &str, body: &str) -> String {
+        let response = client.post(format!("/{}/makethread", board))
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("title", title), ("body", body)]))
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        response.headers().get_one("Location").unwrap().to_owned()
+    }
+
+    #[test]
+    fn empty_index_and_gallery() {
+        let client = client();
+        assert_eq!(client.get("/").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/b/").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/b/gallery").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/b/makethread").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/static/spoiler.svg").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn uploads_come_from_the_media_store() {
+        let client = client();
+        let media = media(&client);
+        let name = format!("{}.thumb.gif", "0".repeat(64));
+        media.put(&name, b"GIF89a", "image/gif").unwrap();
+        let mut response = client.get(format!("/Pictures/{}", name)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::GIF));
+        assert_eq!(response.body_bytes(), Some(b"GIF89a".to_vec()));
+        media.delete(&name).unwrap();
+
+        assert_eq!(client.get(format!("/Pictures/{}", name)).dispatch().status(), Status::NotFound);
+        // only what uploads are stored as
+        media.put("NOTICE.md", b"notice", "text/markdown").unwrap();
+        assert_eq!(client.get("/Pictures/NOTICE.md").dispatch().status(), Status::NotFound);
+    }
+
+    // '/Pictures/makethread' matches both routes, the client could not be built if they collided
+    #[test]
+    fn pictures_next_to_board_routes() {
+        let client = client();
+        assert_eq!(client.get("/b/makethread").dispatch().status(), Status::Ok);
+        let name = format!("{}.png", "0".repeat(64));
+        media(&client).put(&name, &png(1, 1), "image/png").unwrap();
+        let mut response = client.get(format!("/Pictures/{}", name)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(response.body_bytes(), Some(png(1, 1)));
+        assert_eq!(client.get("/Pictures/makethread").dispatch().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn thread_creation_and_reply() {
+        let client = client();
+        let location = make_thread(&client, "hello world", "first!");
+        assert!(location.starts_with("/b/thread/"));
+
+        let index = client.get("/b/").dispatch().body_string().unwrap();
+        assert!(index.contains("hello world"));
+
+        let response = client.post(location.clone())
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", "tester"), ("body", "a reply")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(response.headers().get_one("Location"), Some(location.as_str()));
+
+        let thread = client.get(location.clone()).dispatch().body_string().unwrap();
+        assert!(thread.contains("first!"));
+        assert!(thread.contains("a reply"));
+        // neither has an image
+        assert!(!thread.contains("<img src"));
+
+        let json = client.get(format!("{}/0", location)).dispatch().body_string().unwrap();
+        assert!(json.contains("a reply"));
+        assert!(json.contains("\"attachments\":[]"));
+        let gallery = client.get("/b/gallery").dispatch().body_string().unwrap();
+        assert!(!gallery.contains("imgGallery"));
+    }
+
+    #[test]
+    fn attachment_limits() {
+        let client = client_with(Settings { max_thread_attachments: 1, max_reply_attachments: 0, ..Settings::default() });
+        let response = client.post("/b/makethread")
+            .header(multipart_header())
+            .body(multipart_body_with_files(&[("poster", ""), ("title", "two images"), ("body", "one too many")], &["a.png", "b.png"]))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(storage(&client).count_threads("b", false).unwrap(), 0);
+
+        let form = client.get("/b/makethread").dispatch().body_string().unwrap();
+        assert_eq!(form.matches("type=\"file\"").count(), 1);
+
+        // replies on /b/ can't have images at all
+        let location = make_thread(&client, "text only", "first!");
+        let thread = client.get(location.clone()).dispatch().body_string().unwrap();
+        assert!(!thread.contains("type=\"file\""));
+        let response = client.post(location)
+            .header(multipart_header())
+            .body(multipart_body_with_files(&[("poster", ""), ("body", "a reply")], &["a.png"]))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // unless the board allows more than the settings
+        storage(&client).set_max_attachments("b", Some(4), Some(2)).unwrap();
+        let form = client.get("/b/makethread").dispatch().body_string().unwrap();
+        assert_eq!(form.matches("type=\"file\"").count(), 4);
+    }
+
+    // each file input has its own spoiler checkbox, sending the position of the input
+    #[test]
+    fn spoilers_are_chosen_per_image() {
+        let client = client();
+        let form = client.get("/b/makethread").dispatch().body_string().unwrap();
+        assert!(form.contains("name=\"spoiler\" value=\"0\""));
+        assert!(form.contains("name=\"spoiler\" value=\"3\""));
+
+        let (first, third) = (png(1, 1), png(2, 1));
+        let response = client.post("/b/makethread")
+            .header(multipart_header())
+            .body(multipart_body_with_images(&[("poster", ""), ("title", "spoilers"), ("body", "first!"), ("spoiler", "2")],
+                                             &[("a.png", first.as_slice()), ("", &[] as &[u8]), ("c.png", third.as_slice())]))
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let op = storage(&client).retrieve_op_range("b", false, 0, 1).unwrap().remove(0);
+        let spoilers: Vec<(&str, bool)> = op.attachments.iter().map(|media| (media.original_name.as_str(), media.spoiler)).collect();
+        assert_eq!(spoilers, vec![("a.png", false), ("c.png", true)]);
+    }
+
+    #[test]
+    fn formatted_bodies() {
+        let client = client();
+        let location = make_thread(&client, "formatting", ">be me");
+        client.post(location.clone())
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("body", ">>0 [spoiler]<b>secret</b>[/spoiler]")]))
+            .dispatch();
+
+        let thread = client.get(location.clone()).dispatch().body_string().unwrap();
+        assert!(thread.contains("<span class=\"greentext\">&gt;be me</span>"));
+        assert!(thread.contains(&format!("<a class=\"quotelink\" href=\"{}#p0\">&gt;&gt;0</a>", location)));
+        assert!(thread.contains("<span class=\"spoiler\">&lt;b&gt;secret&lt;/b&gt;</span>"));
+
+        // the API sends the raw body and the rendered one
+        let json = client.get(format!("{}/0", location)).dispatch().body_string().unwrap();
+        assert!(json.contains("\"body\":\">>0 [spoiler]<b>secret</b>[/spoiler]\""));
+        assert!(json.contains("\"body_html\":\"<a class=\\\"quotelink\\\""));
+    }
+
+    #[test]
+    fn backlinks() {
+        let client = client();
+        let location = make_thread(&client, "quoted", "first!");
+        for body in &["a reply", ">>0 >>1 both of you", ">>1 again\n```\n>>2 in code\n```", ">>9 does not exist yet"] {
+            client.post(location.clone())
+                .header(multipart_header())
+                .body(multipart_body(&[("poster", ""), ("body", body)]))
+                .dispatch();
+        }
+
+        let thread = client.get(location.clone()).dispatch().body_string().unwrap();
+        let tile = |postid: i32| {
+            let start = thread.find(&format!("id=\"p{}\"", postid)).unwrap();
+            thread[start..].split("id=\"p").nth(1).unwrap().to_owned()
+        };
+        assert!(tile(0).contains("Replies: <a class=\"quotelink\" href=\"#p2\">&gt;&gt;2</a></div>"));
+        assert!(tile(1).contains("Replies: <a class=\"quotelink\" href=\"#p2\">&gt;&gt;2</a> <a class=\"quotelink\" href=\"#p3\">&gt;&gt;3</a></div>"));
+        assert!(!tile(2).contains("Replies:"));
+        assert!(!thread.contains("href=\"#p4\""));
+
+        let json = client.get(format!("{}/0", location)).dispatch().body_string().unwrap();
+        assert!(json.contains("\"body_html\":\"a reply\",\"replies\":[2,3]"));
+    }
+
+    #[test]
+    fn paginated_views() {
+        let client = client_with(Settings { threads_per_page: 1, last_replies: 1, ..Settings::default() });
+        make_thread(&client, "older thread", "first!");
+        let location = make_thread(&client, "newer thread", "second!");
+
+        let first = client.get("/b/").dispatch().body_string().unwrap();
+        assert!(first.contains("newer thread"));
+        assert!(!first.contains("older thread"));
+        assert!(first.contains("href=\"/b/?page=2\""));
+        let second = client.get("/b/?page=2").dispatch().body_string().unwrap();
+        assert!(second.contains("older thread"));
+        assert_eq!(client.get("/b/?page=3").dispatch().status(), Status::NotFound);
+
+        for body in &["reply one", "reply two"] {
+            client.post(location.clone())
+                .header(multipart_header())
+                .body(multipart_body(&[("poster", ""), ("body", body)]))
+                .dispatch();
+        }
+        let last = client.get(format!("{}?last=1", location)).dispatch().body_string().unwrap();
+        assert!(!last.contains("reply one"));
+        assert!(last.contains("reply two"));
+        assert!(last.contains("View all"));
+    }
+
+    #[test]
+    fn old_threads_are_archived() {
+        let client = client_with(Settings { max_threads: 1, ..Settings::default() });
+        let old = make_thread(&client, "old thread", "first!");
+        make_thread(&client, "new thread", "second!");
+
+        let index = client.get("/b/").dispatch().body_string().unwrap();
+        assert!(!index.contains("old thread"));
+        let archive = client.get("/b/archive").dispatch().body_string().unwrap();
+        assert!(archive.contains("old thread"));
+
+        // still readable, but no longer open for replies
+        let thread = client.get(old.clone()).dispatch().body_string().unwrap();
+        assert!(thread.contains("read-only"));
+        let response = client.post(old)
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("body", "a reply")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn sticky_and_locked_threads() {
+        let client = client();
+        let sticky = make_thread(&client, "rules", "read them");
+        let locked = make_thread(&client, "closed", "nothing to see");
+        make_thread(&client, "latest", "fresh");
+        storage(&client).set_sticky(&1, true).unwrap();
+        storage(&client).set_locked(&2, true).unwrap();
+
+        let index = client.get("/b/").dispatch().body_string().unwrap();
+        assert!(index.find("rules").unwrap() < index.find("latest").unwrap());
+        assert!(index.contains("title=\"Sticky\""));
+        assert!(index.contains("title=\"Locked\""));
+
+        let thread = client.get(locked.clone()).dispatch().body_string().unwrap();
+        assert!(thread.contains("This thread is locked"));
+        let mut response = client.post(locked)
+            .header(multipart_header())
+            .header(Accept::JSON)
+            .body(multipart_body(&[("poster", ""), ("body", "a reply")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(response.body_string().unwrap().contains("locked"));
+
+        let response = client.post(sticky)
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("body", "a reply")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+    }
+
+    #[test]
+    fn search() {
+        let client = client();
+        assert_eq!(client.get("/search").dispatch().status(), Status::Ok);
+        let location = make_thread(&client, "favourite instruments", "the oboe, obviously");
+        client.post(location.clone())
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("body", "a bassoon is just a big oboe")]))
+            .dispatch();
+
+        let results = client.get("/search?q=oboe").dispatch().body_string().unwrap();
+        assert!(results.contains(&format!("href=\"{}#p0\"", location)));
+        assert!(results.contains(&format!("href=\"{}#p1\"", location)));
+        assert!(results.contains("<mark>oboe</mark>"));
+        let results = client.get("/search?q=bassoon").dispatch().body_string().unwrap();
+        assert!(!results.contains("#p0"));
+        assert!(results.contains("#p1"));
+
+        let thread = client.get(location).dispatch().body_string().unwrap();
+        assert!(thread.contains("id=\"p1\""));
+
+        // posters can't open or close the highlighting themselves
+        make_thread(&client, "smuggled", "\u{3}\u{3} clarinet \u{2}unclosed");
+        let results = client.get("/search?q=clarinet").dispatch().body_string().unwrap();
+        assert!(results.contains("<mark>clarinet</mark> unclosed"));
+        assert_eq!(results.matches("<mark>").count(), 1);
+        assert_eq!(results.matches("</mark>").count(), 1);
+    }
+
+    #[test]
+    fn boards_are_separate() {
+        let settings = Settings::default();
+        let storage = MemoryStorage::new(&settings);
+        storage.add_board(&Board {
+            slug: "g".to_owned(),
+            title: "Technology".to_owned(),
+            description: "all things computers".to_owned(),
+            max_threads: None,
+            bump_limit: None,
+            nsfw: true,
+            keep_metadata: false,
+            max_thread_attachments: None,
+            max_reply_attachments: None
+        }).unwrap();
+        let client = client_with_storage(storage, settings);
+
+        let boards = client.get("/").dispatch().body_string().unwrap();
+        assert!(boards.contains("/b/ - Random"));
+        assert!(boards.contains("all things computers"));
+        assert!(boards.contains("NSFW"));
+
+        let location = make_thread_on(&client, "g", "a question", "which editor?");
+        assert!(location.starts_with("/g/thread/"));
+        let technology = client.get("/g/").dispatch().body_string().unwrap();
+        assert!(technology.contains("a question"));
+        assert!(technology.contains("href=\"/b/\""));
+        let random = client.get("/b/").dispatch().body_string().unwrap();
+        assert!(!random.contains("a question"));
+
+        // a thread is only found on its own board
+        let threadid = location.trim_start_matches("/g/thread/");
+        assert_eq!(client.get(format!("/b/thread/{}", threadid)).dispatch().status(), Status::NotFound);
+        let response = client.get(format!("/thread/{}", threadid)).dispatch();
+        assert_eq!(response.status(), Status::PermanentRedirect);
+        assert_eq!(response.headers().get_one("Location"), Some(location.as_str()));
+
+        assert_eq!(client.get("/x/").dispatch().status(), Status::NotFound);
+        let response = client.post("/x/makethread")
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("title", "lost"), ("body", "where am I?")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn invalid_submissions_are_rejected() {
+        let client = client();
+        let response = client.post("/b/makethread")
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("title", ""), ("body", "")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let location = make_thread(&client, "hello world", "first!");
+        let response = client.post(location)
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("body", "")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    // see 'upload::validate', the statuses come from 'OboeError::status'
+    #[test]
+    fn uploads_that_are_refused() {
+        let client = client();
+        let mut response = client.post("/b/makethread")
+            .header(multipart_header())
+            .header(Accept::JSON)
+            .body(multipart_body_with_files(&[("poster", ""), ("title", "not an image"), ("body", "first!")], &["a.png"]))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.body_string().unwrap().contains("Only PNG, JPEG and GIF images can be uploaded."));
+
+        let client = client_with(Settings { max_upload_bytes: 8, ..Settings::default() });
+        let mut response = client.post("/b/makethread")
+            .header(multipart_header())
+            .header(Accept::JSON)
+            .body(multipart_body_with_files(&[("poster", ""), ("title", "too large"), ("body", "first!")], &["a.png"]))
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert!(response.body_string().unwrap().contains("Images can be at most"));
+        assert_eq!(storage(&client).count_threads("b", false).unwrap(), 0);
+    }
+
+    #[test]
+    fn missing_thread_is_not_found() {
+        let client = client();
+        let mut response = client.get("/b/thread/1").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        assert!(response.body_string().unwrap().contains("404"));
+
+        let response = client.post("/b/thread/1")
+            .header(multipart_header())
+            .body(multipart_body(&[("poster", ""), ("body", "a reply")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    // the navigation bar only comes from 'layout.html', none of the pages that used to be static
+    // files have a copy of their own linking the routes from before there were boards
+    #[test]
+    fn every_page_has_the_shared_navigation() {
+        let client = client();
+        for path in &["/", "/b/", "/b/archive", "/b/gallery", "/b/makethread", "/search", "/404", "/500", "/form"] {
+            let page = client.get(*path).dispatch().body_string().unwrap();
+            assert_eq!(page.matches("<nav class=\"nav\">").count(), 1, "{}", path);
+            assert!(page.contains("<form class=\"nav_search\""), "{}", path);
+            for old in &["href=\"/gallery\"", "href=\"/makethread\"", "href=\"/archive\""] {
+                assert!(!page.contains(old), "{} links {}", path, old);
+            }
+        }
+    }
+
+    #[test]
+    fn errors_in_json_when_asked_for() {
+        let client = client();
+        let mut response = client.get("/b/thread/1/0").header(Accept::JSON).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(response.body_string().unwrap(), "{\"status\":404,\"error\":\"The specified thread does not exist.\"}");
+    }
 }
@@ -0,0 +1,164 @@
+use regex::{Captures, Regex};
+
+use crate::html::{Markup, escape};
+
+// The formatting posters can use in a body:
+//     >text                 greentext, for the whole line
+//     >>123                 link to post 123 of the same thread
+//     >>>/board/123         link to thread 123 on another board
+//     [spoiler]text[/spoiler]   hidden until hovered
+//     ```                   lines between two of these are shown as code, without any formatting
+// Everything is escaped first, the patterns are then looked for in the escaped text. That way
+// nothing a poster writes ends up as markup other than the tags added here.
+
+lazy_static! {
+    // cross-board links first, '>>>/b/1' would otherwise be read as '>' followed by a link
+    static ref QUOTE: Regex = Regex::new(r"&gt;&gt;&gt;/([a-z0-9]{1,16})/(\d{1,10})|&gt;&gt;(\d{1,10})").unwrap();
+    static ref SPOILER: Regex = Regex::new(r"\[spoiler\](.*?)\[/spoiler\]").unwrap();
+}
+
+const FENCE: &str = "```";
+
+// the posts of the same thread 'body' links to with '>>123', each once and in the order they are
+// first quoted. Quotes in code blocks are not links, and are left out.
+pub fn quoted_posts(body: &str) -> Vec<i32> {
+    let mut quoted = Vec::new();
+    let mut in_code = false;
+    for line in body.lines() {
+        if line.trim_start().starts_with(FENCE) {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        for captures in QUOTE.captures_iter(&escape(line)) {
+            if let Some(postid) = captures.get(3).and_then(|postid| postid.as_str().parse().ok()) {
+                if !quoted.contains(&postid) {
+                    quoted.push(postid);
+                }
+            }
+        }
+    }
+    quoted
+}
+
+// renders 'body' of a post of the thread at 'thread', the URL '>>123' links are relative to
+pub fn render(body: &str, thread: &str) -> Markup {
+    let mut html = String::new();
+    // lines since the last code block, joined with <br>
+    let mut lines: Vec<String> = Vec::new();
+    // the lines of the code block currently open
+    let mut code: Option<Vec<&str>> = None;
+
+    for line in body.lines() {
+        if line.trim_start().starts_with(FENCE) {
+            match code.take() {
+                Some(block) => html.push_str(&to_code_block(&block)),
+                None => {
+                    flush_lines(&mut html, &mut lines);
+                    code = Some(Vec::new());
+                },
+            }
+            continue;
+        }
+        match code.as_mut() {
+            Some(block) => block.push(line),
+            None => lines.push(render_line(line, thread)),
+        }
+    }
+    // a block that is never closed runs until the end of the body
+    if let Some(block) = code {
+        html.push_str(&to_code_block(&block));
+    }
+    flush_lines(&mut html, &mut lines);
+    Markup::raw(html)
+}
+
+fn flush_lines(html: &mut String, lines: &mut Vec<String>) {
+    if !lines.is_empty() {
+        html.push_str(&lines.join("<br>"));
+        lines.clear();
+    }
+}
+
+fn to_code_block(block: &[&str]) -> String {
+    format!("<pre><code>{}</code></pre>", escape(&block.join("\n")))
+}
+
+// a single line outside of code blocks
+fn render_line(line: &str, thread: &str) -> String {
+    let escaped = escape(line);
+    // '>>123' at the start of a line is a link, not greentext
+    let greentext = escaped.starts_with("&gt;")
+        && QUOTE.find(&escaped).map(|quote| quote.start() != 0).unwrap_or(true);
+
+    let linked = QUOTE.replace_all(&escaped, |captures: &Captures| {
+        match (captures.get(1), captures.get(2), captures.get(3)) {
+            (Some(board), Some(threadid), _) => format!("<a class=\"quotelink\" href=\"/{}/thread/{}\">{}</a>",
+                                                        board.as_str(), threadid.as_str(), &captures[0]),
+            (_, _, Some(postid)) => format!("<a class=\"quotelink\" href=\"{}#p{}\">{}</a>",
+                                            escape(thread), postid.as_str(), &captures[0]),
+            _ => captures[0].to_owned(),
+        }
+    });
+    let spoilered = SPOILER.replace_all(&linked, "<span class=\"spoiler\">$1</span>");
+
+    if greentext {
+        format!("<span class=\"greentext\">{}</span>", spoilered)
+    } else {
+        spoilered.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markup::{quoted_posts, render};
+
+    fn rendered(body: &str) -> String {
+        render(body, "/b/thread/7").into_string()
+    }
+
+    #[test]
+    fn greentext() {
+        assert_eq!(rendered(">be me\nno green"), "<span class=\"greentext\">&gt;be me</span><br>no green");
+        assert_eq!(rendered("a > b"), "a &gt; b");
+    }
+
+    #[test]
+    fn quote_links() {
+        assert_eq!(rendered(">>12 agreed"), "<a class=\"quotelink\" href=\"/b/thread/7#p12\">&gt;&gt;12</a> agreed");
+        assert_eq!(rendered("see >>>/g/34"), "see <a class=\"quotelink\" href=\"/g/thread/34\">&gt;&gt;&gt;/g/34</a>");
+        // links in greentext still work
+        assert_eq!(rendered(">implying >>3"),
+                   "<span class=\"greentext\">&gt;implying <a class=\"quotelink\" href=\"/b/thread/7#p3\">&gt;&gt;3</a></span>");
+        assert_eq!(rendered(">>no number"), "<span class=\"greentext\">&gt;&gt;no number</span>");
+    }
+
+    #[test]
+    fn spoilers() {
+        assert_eq!(rendered("the end: [spoiler]he dies[/spoiler]!"), "the end: <span class=\"spoiler\">he dies</span>!");
+        assert_eq!(rendered("[spoiler]unclosed"), "[spoiler]unclosed");
+    }
+
+    #[test]
+    fn code_blocks() {
+        assert_eq!(rendered("look:\n```\n>not green\nfn main() {}\n```\ndone"),
+                   "look:<pre><code>&gt;not green\nfn main() {}</code></pre>done");
+        assert_eq!(rendered("```\nunclosed <b>"), "<pre><code>unclosed &lt;b&gt;</code></pre>");
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(quoted_posts(">>3 and >>1\n>>3 again, >>>/g/5 elsewhere"), vec![3, 1]);
+        assert_eq!(quoted_posts("```\n>>2\n```\n>>99999999999"), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn markup_is_escaped() {
+        let html = rendered("<script>alert(1)</script> [spoiler]<img src=x onerror=alert(1)>[/spoiler]");
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<img"));
+        assert!(html.contains("<span class=\"spoiler\">&lt;img"));
+    }
+}
@@ -0,0 +1,140 @@
+use std::{fs, process};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::config::Settings;
+use crate::error::{OboeError, Result};
+use crate::s3::S3Store;
+use crate::upload;
+
+// Uploaded images and their thumbnails are kept in a 'MediaStore', by the name of their file, e.g.
+// '9f86d0…0a08.png'. 'Media' refers to them as 'Pictures/<name>', which is also where this server
+// serves them, see the 'pictures' route. Rocket keeps one boxed instance in its managed state.
+pub trait MediaStore: Send + Sync {
+    // stores 'content' of the type 'mime' under 'name', replacing what was there
+    fn put(&self, name: &str, content: &[u8], mime: &str) -> Result<()>;
+
+    // the content stored under 'name', 'None' if there is none
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>>;
+
+    fn exists(&self, name: &str) -> Result<bool>;
+
+    // removes what is stored under 'name'. Names that were never stored are no error.
+    fn delete(&self, name: &str) -> Result<()>;
+}
+
+// 'MediaStore' keeping every file in a directory of the local file system
+pub struct LocalStore {
+    directory: PathBuf
+}
+
+impl LocalStore {
+    pub fn new(directory: &str) -> LocalStore {
+        LocalStore { directory: PathBuf::from(directory) }
+    }
+
+    // the file stored under 'name'. Names are plain file names, nothing outside the directory can be reached.
+    fn file(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(|c| c == '/' || c == '\\') {
+            return Err(OboeError::NotFound(format!("\"{}\" is not the name of a stored file.", name)));
+        }
+        Ok(self.directory.join(name))
+    }
+}
+
+impl MediaStore for LocalStore {
+    fn put(&self, name: &str, content: &[u8], _mime: &str) -> Result<()> {
+        Ok(fs::write(self.file(name)?, content)?)
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.file(name)?) {
+            Ok(content) => Ok(Some(content)),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.file(name)?.exists())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.file(name)?) {
+            Ok(()) => Ok(()),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+// The store is chosen by the 'media_store' key of the rocket config (Rocket.toml or ROCKET_MEDIA_STORE).
+// "local" is the default and keeps images in 'Pictures/', "s3" in the bucket set by the 's3_*' keys.
+pub fn from_settings(settings: &Settings) -> Box<dyn MediaStore> {
+    match settings.media_store.as_str() {
+        "local" => Box::new(LocalStore::new(upload::DIRECTORY)),
+        "s3" => Box::new(S3Store::new(settings)),
+        other => {
+            println!("Unknown media store \"{}\", use either \"local\" or \"s3\".", other);
+            process::exit(1);
+        },
+    }
+}
+
+// Where pages and the JSON API tell clients to load images from: the 'pictures' route of this
+// server, or the 'media_url' setting followed by the name of the file. The default is this server.
+#[derive(Clone, Copy, Default)]
+pub struct MediaUrls<'a> {
+    base: Option<&'a str>
+}
+
+impl<'a> MediaUrls<'a> {
+    pub fn new(settings: &'a Settings) -> MediaUrls<'a> {
+        MediaUrls { base: settings.media_url.as_ref().map(|url| url.trim_end_matches('/')) }
+    }
+
+    // the URL of the image or thumbnail at 'path', as 'Media' has it
+    pub fn of(&self, path: &str) -> String {
+        match self.base {
+            Some(base) => format!("{}/{}", base, upload::name_of(path)),
+            None => format!("/{}", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::config::Settings;
+    use crate::media::{LocalStore, MediaStore, MediaUrls};
+
+    #[test]
+    fn local_files() {
+        let directory = env::temp_dir().join(format!("oboe-media-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let store = LocalStore::new(&directory.to_string_lossy());
+
+        assert!(!store.exists("a.png").unwrap());
+        assert!(store.get("a.png").unwrap().is_none());
+        store.put("a.png", b"content", "image/png").unwrap();
+        assert!(store.exists("a.png").unwrap());
+        assert_eq!(store.get("a.png").unwrap().unwrap(), b"content");
+        store.delete("a.png").unwrap();
+        assert!(!store.exists("a.png").unwrap());
+        store.delete("a.png").unwrap();
+
+        // only names inside the directory
+        assert!(store.get("../a.png").is_err());
+        assert!(store.put(".hidden", b"content", "image/png").is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn urls() {
+        let settings = Settings::default();
+        assert_eq!(MediaUrls::new(&settings).of("Pictures/a.png"), "/Pictures/a.png");
+        let settings = Settings { media_url: Some("https://cdn.example.com/images/".to_owned()), ..Settings::default() };
+        assert_eq!(MediaUrls::new(&settings).of("Pictures/a.thumb.png"), "https://cdn.example.com/images/a.thumb.png");
+    }
+}
@@ -0,0 +1,698 @@
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::Settings;
+use crate::error::{OboeError, Result};
+use crate::postgres::{Backlinks, Board, Media, OP, Post, SearchHit, Thread};
+use crate::storage::{Storage, DEFAULT_BOARD, HIGHLIGHT_START, HIGHLIGHT_END, check_slug, find_board, quoted_posts, random_threadid, thread_not_found, thread_archived, thread_locked, without_highlights};
+
+// 'Storage' that keeps every thread and post in memory. Nothing survives a restart, so this is
+// meant for tests and trying out the board without setting up PostgreSQL.
+// Like a freshly migrated DB, it starts out with only the default board.
+pub struct MemoryStorage {
+    boards: RwLock<Vec<Board>>,
+    threads: RwLock<Vec<OP>>,
+    posts: RwLock<Vec<Post>>,
+    // one path for every claim of an image, see 'Storage::claim_image'
+    claims: Mutex<Vec<String>>,
+    // the paths of the images of purged threads and their thumbnails, see 'Storage::discarded_media'
+    discarded: RwLock<Vec<(String, String)>>,
+    // the last thread id given out, like 'threads_threadid_seq' ids of purged threads are not reused
+    last_threadid: AtomicI32,
+    random_thread_ids: bool,
+    bump_limit: i32
+}
+
+impl MemoryStorage {
+    pub fn new(settings: &Settings) -> MemoryStorage {
+        let default_board = Board {
+            slug: DEFAULT_BOARD.to_owned(),
+            title: "Random".to_owned(),
+            description: String::new(),
+            max_threads: None,
+            bump_limit: None,
+            nsfw: false,
+            keep_metadata: false,
+            max_thread_attachments: None,
+            max_reply_attachments: None
+        };
+        MemoryStorage {
+            boards: RwLock::new(vec![default_board]),
+            threads: RwLock::new(Vec::new()),
+            posts: RwLock::new(Vec::new()),
+            claims: Mutex::new(Vec::new()),
+            discarded: RwLock::new(Vec::new()),
+            last_threadid: AtomicI32::new(0),
+            random_thread_ids: settings.random_thread_ids,
+            bump_limit: settings.bump_limit
+        }
+    }
+
+    // every opening post and post matching all words of 'query', with its rank.
+    // A plain scan, the memory backend has no index to search with.
+    fn scan(&self, query: &str) -> Vec<(usize, SearchHit)> {
+        let terms = search_terms(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let threads = self.threads.read().unwrap();
+        let mut hits = Vec::new();
+        for op in threads.iter() {
+            if let Some(rank) = relevance(&terms, &format!("{} {}", op.title, op.body)) {
+                hits.push((rank, SearchHit {
+                    board: op.board.clone(),
+                    threadid: op.threadid,
+                    postid: 0,
+                    title: op.title.clone(),
+                    snippet: highlight(&terms, &without_highlights(&op.body)),
+                    created_at: op.created_at
+                }));
+            }
+        }
+        for post in self.posts.read().unwrap().iter() {
+            if let (Some(rank), Some(op)) = (relevance(&terms, &post.body), threads.iter().find(|op| op.threadid == post.threadid)) {
+                hits.push((rank, SearchHit {
+                    board: op.board.clone(),
+                    threadid: post.threadid,
+                    postid: post.postid,
+                    title: op.title.clone(),
+                    snippet: highlight(&terms, &without_highlights(&post.body)),
+                    created_at: post.created_at
+                }));
+            }
+        }
+        hits.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.created_at.cmp(&a.1.created_at)));
+        hits
+    }
+}
+
+// the lowercased words of a search query, without the quotes and operators postgres understands
+fn search_terms(query: &str) -> Vec<String> {
+    query.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+// how often the 'terms' occur in 'text', or 'None' if any of them is missing
+fn relevance(terms: &[String], text: &str) -> Option<usize> {
+    let text = text.to_lowercase();
+    let counts: Vec<usize> = terms.iter().map(|term| text.matches(term.as_str()).count()).collect();
+    if counts.contains(&0) {
+        return None;
+    }
+    Some(counts.iter().sum())
+}
+
+// about 30 words of 'text' around the first match, with every matching word marked
+fn highlight(terms: &[String], text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let matches = |word: &str| {
+        let word = word.to_lowercase();
+        terms.iter().any(|term| word.contains(term.as_str()))
+    };
+    let first = words.iter().position(|word| matches(word)).unwrap_or(0);
+    let start = first.saturating_sub(10);
+    let end = (start + 30).min(words.len());
+    let marked: Vec<String> = words[start..end].iter()
+        .map(|word| if matches(word) { format!("{}{}{}", HIGHLIGHT_START, word, HIGHLIGHT_END) } else { word.to_string() })
+        .collect();
+    marked.join(" ")
+}
+
+impl Storage for MemoryStorage {
+    fn add_board(&self, board: &Board) -> Result<()> {
+        check_slug(&board.slug)?;
+        let mut boards = self.boards.write().unwrap();
+        if boards.iter().any(|existing| existing.slug == board.slug) {
+            return Err(OboeError::Validation(format!("The board /{}/ already exists.", board.slug)));
+        }
+        boards.push(board.clone());
+        boards.sort_by(|a, b| a.slug.cmp(&b.slug));
+        Ok(())
+    }
+
+    fn retrieve_boards(&self) -> Result<Vec<Board>> {
+        Ok(self.boards.read().unwrap().clone())
+    }
+
+    fn set_keep_metadata(&self, slug: &str, keep_metadata: bool) -> Result<()> {
+        let mut boards = self.boards.write().unwrap();
+        let board = boards.iter_mut().find(|board| board.slug == slug)
+            .ok_or_else(|| OboeError::NotFound(format!("There is no board /{}/.", slug)))?;
+        board.keep_metadata = keep_metadata;
+        Ok(())
+    }
+
+    fn set_max_attachments(&self, slug: &str, thread: Option<i32>, reply: Option<i32>) -> Result<()> {
+        let mut boards = self.boards.write().unwrap();
+        let board = boards.iter_mut().find(|board| board.slug == slug)
+            .ok_or_else(|| OboeError::NotFound(format!("There is no board /{}/.", slug)))?;
+        board.max_thread_attachments = thread;
+        board.max_reply_attachments = reply;
+        Ok(())
+    }
+
+    fn add_thread(&self, op: &OP) -> Result<i32> {
+        let mut claims = self.claims.lock().unwrap();
+        find_board(&self.boards.read().unwrap(), &op.board)?;
+        let mut threads = self.threads.write().unwrap();
+        let taken = |threadid: i32| threads.iter().any(|existing| existing.threadid == threadid);
+        let candidate = || if self.random_thread_ids {
+            random_threadid()
+        } else {
+            self.last_threadid.fetch_add(1, Ordering::SeqCst) + 1
+        };
+        let mut threadid = candidate();
+        while taken(threadid) {
+            threadid = candidate();
+        }
+        threads.push(OP { threadid, last_bump: op.created_at, post_count: 0, archived_at: None, sticky: false, locked: false, ..op.clone() });
+        for media in op.attachments.iter() {
+            release(&mut claims, &media.path);
+        }
+        Ok(threadid)
+    }
+
+    // holding the write locks while numbering makes allocation and insertion one step.
+    // Locks are always taken in the order claims, boards, threads, posts, then discarded.
+    fn add_post(&self, post: &Post) -> Result<i32> {
+        let mut claims = self.claims.lock().unwrap();
+        let boards = self.boards.read().unwrap();
+        let mut threads = self.threads.write().unwrap();
+        let op = threads.iter_mut()
+            .find(|op| op.threadid == post.threadid)
+            .ok_or_else(thread_not_found)?;
+        if op.archived_at.is_some() {
+            return Err(thread_archived());
+        }
+        if op.locked {
+            return Err(thread_locked());
+        }
+        let bump_limit = find_board(&boards, &op.board)?.bump_limit.unwrap_or(self.bump_limit);
+        let mut posts = self.posts.write().unwrap();
+        if !post.sage && op.post_count < bump_limit {
+            op.last_bump = post.created_at;
+        }
+        op.post_count += 1;
+        let postid = op.post_count;
+        posts.push(Post { postid, sage: false, ..post.clone() });
+        for media in post.attachments.iter() {
+            release(&mut claims, &media.path);
+        }
+        Ok(postid)
+    }
+
+    fn retrieve_op(&self, threadid: &i32) -> Result<Option<OP>> {
+        Ok(self.threads.read().unwrap().iter()
+            .find(|op| op.threadid == *threadid)
+            .cloned())
+    }
+
+    fn retrieve_op_range(&self, board: &str, archived: bool, offset: i64, limit: i64) -> Result<Vec<OP>> {
+        let mut threads: Vec<OP> = self.threads.read().unwrap().iter()
+            .filter(|op| op.board == board && op.archived_at.is_some() == archived)
+            .cloned()
+            .collect();
+        if archived {
+            threads.sort_by(|a, b| b.archived_at.cmp(&a.archived_at));
+        } else {
+            threads.sort_by(|a, b| b.sticky.cmp(&a.sticky).then(b.last_bump.cmp(&a.last_bump)));
+        }
+        Ok(threads.into_iter().skip(offset as usize).take(limit as usize).collect())
+    }
+
+    fn count_threads(&self, board: &str, archived: bool) -> Result<i64> {
+        Ok(self.threads.read().unwrap().iter().filter(|op| op.board == board && op.archived_at.is_some() == archived).count() as i64)
+    }
+
+    fn archive_overflow(&self, board: &str, max_threads: i64) -> Result<()> {
+        let mut threads = self.threads.write().unwrap();
+        let mut live: Vec<&mut OP> = threads.iter_mut().filter(|op| op.board == board && op.archived_at.is_none() && !op.sticky).collect();
+        live.sort_by(|a, b| b.last_bump.cmp(&a.last_bump));
+        let now = Utc::now();
+        for op in live.into_iter().skip(max_threads as usize) {
+            op.archived_at = Some(now);
+        }
+        Ok(())
+    }
+
+    fn set_sticky(&self, threadid: &i32, sticky: bool) -> Result<()> {
+        let mut threads = self.threads.write().unwrap();
+        let op = threads.iter_mut().find(|op| op.threadid == *threadid).ok_or_else(thread_not_found)?;
+        op.sticky = sticky;
+        Ok(())
+    }
+
+    fn set_locked(&self, threadid: &i32, locked: bool) -> Result<()> {
+        let mut threads = self.threads.write().unwrap();
+        let op = threads.iter_mut().find(|op| op.threadid == *threadid).ok_or_else(thread_not_found)?;
+        op.locked = locked;
+        Ok(())
+    }
+
+    fn purge_archived(&self, before: DateTime<Utc>) -> Result<Vec<Thread>> {
+        let mut threads = self.threads.write().unwrap();
+        let mut posts = self.posts.write().unwrap();
+        let (purged, kept): (Vec<OP>, Vec<OP>) = threads.drain(..)
+            .partition(|op| op.archived_at.map(|archived_at| archived_at < before).unwrap_or(false));
+        *threads = kept;
+
+        let purged: Vec<Thread> = purged.into_iter().map(|op| {
+            let (own, rest): (Vec<Post>, Vec<Post>) = posts.drain(..).partition(|post| post.threadid == op.threadid);
+            *posts = rest;
+            Thread { op, posts: if own.is_empty() { None } else { Some(own) } }
+        }).collect();
+        let mut discarded = self.discarded.write().unwrap();
+        for thread in purged.iter() {
+            let posts = thread.posts.iter().flatten().flat_map(|post| post.attachments.iter());
+            for media in thread.op.attachments.iter().chain(posts) {
+                if !discarded.iter().any(|(path, _)| *path == media.path) {
+                    discarded.push((media.path.clone(), media.thumbnail.clone()));
+                }
+            }
+        }
+        Ok(purged)
+    }
+
+    fn discarded_media(&self) -> Result<Vec<(String, String)>> {
+        let mut discarded = self.discarded.read().unwrap().clone();
+        discarded.sort();
+        Ok(discarded)
+    }
+
+    fn forget_discarded(&self, path: &str) -> Result<()> {
+        self.discarded.write().unwrap().retain(|(discarded, _)| discarded != path);
+        Ok(())
+    }
+
+    fn search(&self, query: &str, offset: i64, limit: i64) -> Result<Vec<SearchHit>> {
+        Ok(self.scan(query).into_iter().skip(offset as usize).take(limit as usize).map(|(_, hit)| hit).collect())
+    }
+
+    fn count_search(&self, query: &str) -> Result<i64> {
+        Ok(self.scan(query).len() as i64)
+    }
+
+    fn retrieve_posts(&self, threadid: &i32) -> Result<Option<Vec<Post>>> {
+        let posts: Vec<Post> = self.posts.read().unwrap().iter()
+            .filter(|post| post.threadid == *threadid)
+            .cloned()
+            .collect();
+        if posts.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(posts))
+    }
+
+    // nothing is stored for this, the bodies are simply parsed again
+    fn retrieve_backlinks(&self, threadid: &i32) -> Result<Backlinks> {
+        let mut backlinks = Backlinks::default();
+        for post in self.posts.read().unwrap().iter().filter(|post| post.threadid == *threadid) {
+            for quoted in quoted_posts(&post.body, post.postid) {
+                backlinks.add(quoted, post.postid);
+            }
+        }
+        Ok(backlinks)
+    }
+
+    fn retrieve_last_posts(&self, threadid: &i32, count: i64) -> Result<Option<Vec<Post>>> {
+        Ok(self.retrieve_posts(threadid)?.map(|posts| {
+            let skip = posts.len().saturating_sub(count as usize);
+            posts.into_iter().skip(skip).collect()
+        }))
+    }
+
+    fn retrieve_image_range(&self, board: &str, offset: i64, limit: i64) -> Result<Vec<Media>> {
+        let threads = self.threads.read().unwrap();
+        let on_board: Vec<&OP> = threads.iter().filter(|op| op.board == board).collect();
+        let mut images: Vec<(DateTime<Utc>, Media)> = on_board.iter()
+            .flat_map(|op| op.attachments.iter().map(move |media| (op.created_at, media.clone())))
+            .collect();
+        images.extend(self.posts.read().unwrap().iter()
+            .filter(|post| on_board.iter().any(|op| op.threadid == post.threadid))
+            .flat_map(|post| post.attachments.iter().map(move |media| (post.created_at, media.clone()))));
+        // stable, the images of a post stay in their order
+        images.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(images.into_iter().skip(offset as usize).take(limit as usize).map(|(_, media)| media).collect())
+    }
+
+    fn count_images(&self, board: &str) -> Result<i64> {
+        Ok(self.retrieve_image_range(board, 0, i64::max_value())?.len() as i64)
+    }
+
+    fn claim_image(&self, path: &str) -> Result<()> {
+        self.claims.lock().unwrap().push(path.to_owned());
+        Ok(())
+    }
+
+    fn release_image(&self, path: &str) -> Result<()> {
+        release(&mut self.claims.lock().unwrap(), path);
+        Ok(())
+    }
+
+    // holding the claims while removing makes checking and removing one step
+    fn remove_unused_image(&self, path: &str, remove: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        let claims = self.claims.lock().unwrap();
+        let has = |attachments: &[Media]| attachments.iter().any(|media| media.path == path);
+        let in_use = claims.iter().any(|claimed| claimed == path)
+            || self.threads.read().unwrap().iter().any(|op| has(&op.attachments))
+            || self.posts.read().unwrap().iter().any(|post| has(&post.attachments));
+        if !in_use {
+            remove()?;
+        }
+        Ok(())
+    }
+}
+
+// takes one claim of the image at 'path' off 'claims'
+fn release(claims: &mut Vec<String>, path: &str) {
+    if let Some(position) = claims.iter().position(|claimed| claimed == path) {
+        claims.remove(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use crate::config::Settings;
+    use crate::memory::MemoryStorage;
+    use crate::postgres::{Board, Media, OP, Post};
+    use crate::storage::{Storage, HIGHLIGHT_START, HIGHLIGHT_END};
+
+    fn dummy_media(name: &str) -> Media {
+        Media {
+            id: 0,
+            hash: String::new(),
+            mime: "image/png".to_owned(),
+            width: 1,
+            height: 1,
+            byte_size: 68,
+            original_name: name.to_owned(),
+            path: format!("Pictures/{}", name),
+            thumbnail: format!("Pictures/{}.thumb.png", name),
+            spoiler: false
+        }
+    }
+
+    // threads and posts have an image each, unless a test says otherwise
+    fn dummy_thread() -> OP {
+        OP {
+            threadid: 0,
+            board: "b".to_owned(),
+            poster: "Anonymous".to_owned(),
+            title: "this is a thread".to_owned(),
+            body: "this is a thread body".to_owned(),
+            attachments: vec![dummy_media("op.png")],
+            created_at: Utc::now(),
+            last_bump: Utc::now(),
+            post_count: 0,
+            archived_at: None,
+            sticky: false,
+            locked: false
+        }
+    }
+
+    fn dummy_post(threadid: i32) -> Post {
+        Post {
+            threadid,
+            poster: "Anonymous".to_owned(),
+            body: "this is a post body".to_owned(),
+            attachments: vec![dummy_media("post.png")],
+            created_at: Utc::now(),
+            postid: 0,
+            sage: false
+        }
+    }
+
+    #[test]
+    fn thread_roundtrip() {
+        let storage = MemoryStorage::new(&Settings::default());
+        assert_eq!(storage.add_thread(&dummy_thread()).unwrap(), 1);
+        assert_eq!(storage.add_thread(&dummy_thread()).unwrap(), 2);
+
+        assert_eq!(storage.retrieve_op(&1).unwrap().unwrap().title, "this is a thread");
+        assert!(storage.retrieve_op(&3).unwrap().is_none());
+        assert_eq!(storage.count_threads("b", false).unwrap(), 2);
+    }
+
+    #[test]
+    fn random_thread_ids() {
+        let settings = Settings { random_thread_ids: true, ..Settings::default() };
+        let storage = MemoryStorage::new(&settings);
+        let mut threadids: Vec<i32> = (0..100).map(|_| storage.add_thread(&dummy_thread()).unwrap()).collect();
+        assert!(threadids.iter().all(|threadid| *threadid > 0));
+        threadids.sort();
+        threadids.dedup();
+        assert_eq!(threadids.len(), 100);
+    }
+
+    #[test]
+    fn posts_belong_to_their_thread() {
+        let storage = MemoryStorage::new(&Settings::default());
+        storage.add_thread(&dummy_thread()).unwrap();
+        storage.add_thread(&dummy_thread()).unwrap();
+        assert_eq!(storage.add_post(&dummy_post(1)).unwrap(), 1);
+        assert_eq!(storage.add_post(&dummy_post(1)).unwrap(), 2);
+        assert!(storage.add_post(&dummy_post(3)).is_err());
+
+        assert_eq!(storage.retrieve_posts(&1).unwrap().unwrap().len(), 2);
+        assert!(storage.retrieve_posts(&2).unwrap().is_none());
+        assert_eq!(storage.count_images("b").unwrap(), 4);
+
+        let thread = storage.retrieve_thread(&1).unwrap();
+        assert_eq!(thread.op.threadid, 1);
+        assert_eq!(thread.posts.unwrap().len(), 2);
+        assert!(storage.retrieve_thread(&3).is_err());
+    }
+
+    // the thread ids of the index, top to bottom
+    fn index_order(storage: &MemoryStorage) -> Vec<i32> {
+        storage.retrieve_op_range("b", false, 0, 100).unwrap().iter().map(|op| op.threadid).collect()
+    }
+
+    // a thread created 'seconds' after the epoch, and a reply to 'threadid' made at that time
+    fn thread_at(seconds: i64) -> OP {
+        OP { created_at: Utc.timestamp(seconds, 0), ..dummy_thread() }
+    }
+
+    fn post_at(threadid: i32, seconds: i64) -> Post {
+        Post { created_at: Utc.timestamp(seconds, 0), ..dummy_post(threadid) }
+    }
+
+    #[test]
+    fn replies_bump_threads() {
+        let storage = MemoryStorage::new(&Settings::default());
+        storage.add_thread(&thread_at(1)).unwrap();
+        storage.add_thread(&thread_at(2)).unwrap();
+        assert_eq!(index_order(&storage), vec![2, 1]);
+
+        storage.add_post(&post_at(1, 3)).unwrap();
+        assert_eq!(index_order(&storage), vec![1, 2]);
+
+        // saged replies do not bump
+        storage.add_post(&post_at(2, 4)).unwrap();
+        storage.add_post(&Post { sage: true, ..post_at(1, 5) }).unwrap();
+        assert_eq!(index_order(&storage), vec![2, 1]);
+    }
+
+    #[test]
+    fn bump_limit() {
+        let settings = Settings { bump_limit: 1, ..Settings::default() };
+        let storage = MemoryStorage::new(&settings);
+        storage.add_thread(&thread_at(1)).unwrap();
+        storage.add_thread(&thread_at(2)).unwrap();
+        storage.add_post(&post_at(1, 3)).unwrap();
+        storage.add_post(&post_at(2, 4)).unwrap();
+        assert_eq!(index_order(&storage), vec![2, 1]);
+
+        // the second reply to thread 1 is past the limit
+        storage.add_post(&post_at(1, 5)).unwrap();
+        assert_eq!(index_order(&storage), vec![2, 1]);
+    }
+
+    #[test]
+    fn pages() {
+        let storage = MemoryStorage::new(&Settings::default());
+        for seconds in 1..=5 {
+            storage.add_thread(&thread_at(seconds)).unwrap();
+        }
+        let first = storage.retrieve_index_page("b", false, 1, 2).unwrap();
+        assert_eq!(first.pages, 3);
+        assert_eq!(first.items.iter().map(|op| op.threadid).collect::<Vec<i32>>(), vec![5, 4]);
+        let last = storage.retrieve_index_page("b", false, 3, 2).unwrap();
+        assert_eq!(last.items.iter().map(|op| op.threadid).collect::<Vec<i32>>(), vec![1]);
+        assert!(storage.retrieve_index_page("b", false, 4, 2).is_err());
+
+        assert_eq!(storage.retrieve_gallery_page("b", 1, 10).unwrap().items.len(), 5);
+    }
+
+    #[test]
+    fn last_posts() {
+        let storage = MemoryStorage::new(&Settings::default());
+        storage.add_thread(&dummy_thread()).unwrap();
+        for _ in 0..5 {
+            storage.add_post(&dummy_post(1)).unwrap();
+        }
+        let thread = storage.retrieve_thread_tail(&1, 2).unwrap();
+        assert_eq!(thread.op.post_count, 5);
+        assert_eq!(thread.posts.unwrap().iter().map(|post| post.postid).collect::<Vec<i32>>(), vec![4, 5]);
+    }
+
+    #[test]
+    fn archive_and_purge() {
+        let storage = MemoryStorage::new(&Settings::default());
+        for seconds in 1..=3 {
+            storage.add_thread(&thread_at(seconds)).unwrap();
+        }
+        storage.add_post(&post_at(1, 4)).unwrap();
+
+        // thread 2 is the least recently bumped
+        storage.archive_overflow("b", 2).unwrap();
+        assert_eq!(index_order(&storage), vec![1, 3]);
+        assert_eq!(storage.count_threads("b", true).unwrap(), 1);
+        assert!(storage.retrieve_op(&2).unwrap().unwrap().archived_at.is_some());
+        assert!(storage.add_post(&dummy_post(2)).is_err());
+
+        // nothing was archived before the epoch
+        assert!(storage.purge_archived(Utc.timestamp(0, 0)).unwrap().is_empty());
+        let purged = storage.purge_archived(Utc::now() + chrono::Duration::seconds(1)).unwrap();
+        assert_eq!(purged.len(), 1);
+        assert_eq!(purged[0].op.threadid, 2);
+        assert!(storage.retrieve_op(&2).unwrap().is_none());
+        assert_eq!(storage.count_threads("b", false).unwrap(), 2);
+        // the id of a purged thread is not given out again, even if it was the highest
+        storage.archive_overflow("b", 1).unwrap();
+        storage.purge_archived(Utc::now() + chrono::Duration::seconds(1)).unwrap();
+        assert_eq!(storage.add_thread(&thread_at(5)).unwrap(), 4);
+
+        // the image of the purged threads stays discarded until its files are deleted
+        let discarded = vec![("Pictures/op.png".to_owned(), "Pictures/op.png.thumb.png".to_owned())];
+        assert_eq!(storage.discarded_media().unwrap(), discarded);
+        storage.forget_discarded("Pictures/op.png").unwrap();
+        assert!(storage.discarded_media().unwrap().is_empty());
+    }
+
+    #[test]
+    fn boards_are_separate() {
+        let storage = MemoryStorage::new(&Settings::default());
+        storage.add_board(&Board {
+            slug: "g".to_owned(),
+            title: "Technology".to_owned(),
+            description: String::new(),
+            max_threads: None,
+            bump_limit: Some(0),
+            nsfw: false,
+            keep_metadata: false,
+            max_thread_attachments: None,
+            max_reply_attachments: None
+        }).unwrap();
+        assert!(storage.add_board(&Board { title: "again".to_owned(), ..storage.retrieve_board("g").unwrap() }).is_err());
+        assert_eq!(storage.retrieve_boards().unwrap().iter().map(|board| board.slug.as_str()).collect::<Vec<&str>>(), vec!["b", "g"]);
+
+        storage.add_thread(&thread_at(1)).unwrap();
+        storage.add_thread(&OP { board: "g".to_owned(), ..thread_at(2) }).unwrap();
+        assert!(storage.add_thread(&OP { board: "x".to_owned(), ..dummy_thread() }).is_err());
+        assert_eq!(index_order(&storage), vec![1]);
+        assert_eq!(storage.count_threads("g", false).unwrap(), 1);
+        assert!(storage.retrieve_op_on("g", &1).is_err());
+
+        // with a bump limit of 0 on /g/, no reply bumps its threads
+        storage.add_post(&post_at(2, 3)).unwrap();
+        assert_eq!(storage.retrieve_op(&2).unwrap().unwrap().last_bump, Utc.timestamp(2, 0));
+        assert_eq!(storage.count_images("g").unwrap(), 2);
+
+        storage.archive_overflow("g", 0).unwrap();
+        assert_eq!(storage.count_threads("b", false).unwrap(), 1);
+    }
+
+    #[test]
+    fn sticky_and_locked() {
+        let storage = MemoryStorage::new(&Settings::default());
+        for seconds in 1..=3 {
+            storage.add_thread(&thread_at(seconds)).unwrap();
+        }
+        storage.set_sticky(&1, true).unwrap();
+        assert_eq!(index_order(&storage), vec![1, 3, 2]);
+        assert!(storage.set_sticky(&4, true).is_err());
+
+        // the sticky is neither archived nor counted
+        storage.archive_overflow("b", 1).unwrap();
+        assert_eq!(index_order(&storage), vec![1, 3]);
+
+        storage.set_locked(&3, true).unwrap();
+        assert!(storage.add_post(&dummy_post(3)).is_err());
+        storage.set_locked(&3, false).unwrap();
+        assert_eq!(storage.add_post(&dummy_post(3)).unwrap(), 1);
+    }
+
+    #[test]
+    fn attachments() {
+        let storage = MemoryStorage::new(&Settings::default());
+        storage.add_thread(&OP { attachments: vec![dummy_media("a.png"), dummy_media("b.png")], ..thread_at(1) }).unwrap();
+        storage.add_post(&Post { attachments: Vec::new(), ..post_at(1, 2) }).unwrap();
+        storage.add_post(&Post { attachments: vec![dummy_media("c.png"), dummy_media("d.png"), dummy_media("e.png")], ..post_at(1, 3) }).unwrap();
+
+        assert_eq!(storage.retrieve_op(&1).unwrap().unwrap().attachments.len(), 2);
+        assert!(storage.retrieve_posts(&1).unwrap().unwrap()[0].attachments.is_empty());
+        // every image is listed on its own, the newest post first
+        assert_eq!(storage.count_images("b").unwrap(), 5);
+        let names: Vec<String> = storage.retrieve_image_range("b", 0, 10).unwrap().into_iter().map(|media| media.original_name).collect();
+        assert_eq!(names, vec!["c.png", "d.png", "e.png", "a.png", "b.png"]);
+        // the files of images a post has are kept
+        let mut removed = Vec::new();
+        for path in &["Pictures/d.png", "Pictures/f.png"] {
+            storage.remove_unused_image(path, &mut || { removed.push(path.to_string()); Ok(()) }).unwrap();
+        }
+        assert_eq!(removed, vec!["Pictures/f.png"]);
+    }
+
+    #[test]
+    fn claimed_images_are_kept() {
+        let storage = MemoryStorage::new(&Settings::default());
+        let mut removed = 0;
+        storage.claim_image("Pictures/a.png").unwrap();
+        storage.claim_image("Pictures/a.png").unwrap();
+        storage.remove_unused_image("Pictures/a.png", &mut || { removed += 1; Ok(()) }).unwrap();
+        // adding a thread with the image turns one claim into an attachment
+        storage.add_thread(&OP { attachments: vec![dummy_media("a.png")], ..thread_at(1) }).unwrap();
+        storage.remove_unused_image("Pictures/a.png", &mut || { removed += 1; Ok(()) }).unwrap();
+        assert_eq!(removed, 0);
+
+        // the other claim is released, but the thread still has it
+        storage.release_image("Pictures/a.png").unwrap();
+        storage.remove_unused_image("Pictures/a.png", &mut || { removed += 1; Ok(()) }).unwrap();
+        assert_eq!(removed, 0);
+        storage.claim_image("Pictures/b.png").unwrap();
+        storage.release_image("Pictures/b.png").unwrap();
+        storage.remove_unused_image("Pictures/b.png", &mut || { removed += 1; Ok(()) }).unwrap();
+        assert_eq!(removed, 1);
+    }
+
+    #[test]
+    fn search() {
+        let storage = MemoryStorage::new(&Settings::default());
+        storage.add_thread(&OP { title: "Rust compilers".to_owned(), body: "which one do you use?".to_owned(), ..thread_at(1) }).unwrap();
+        storage.add_thread(&OP { title: "cooking".to_owned(), body: "rust on my pan".to_owned(), ..thread_at(2) }).unwrap();
+        storage.add_post(&Post { body: "rustc, rustc and nothing else".to_owned(), ..post_at(1, 3) }).unwrap();
+
+        // the reply mentions it most often
+        let hits = storage.search("rust", 0, 10).unwrap();
+        assert_eq!(hits.iter().map(|hit| (hit.threadid, hit.postid)).collect::<Vec<(i32, i32)>>(), vec![(1, 1), (2, 0), (1, 0)]);
+        assert_eq!(hits[0].title, "Rust compilers");
+        assert_eq!(hits[1].snippet, format!("{}rust{} on my pan", HIGHLIGHT_START, HIGHLIGHT_END));
+
+        // markers in a body are not taken for highlights
+        let body = format!("{}{} oboe {}unclosed", HIGHLIGHT_END, HIGHLIGHT_END, HIGHLIGHT_START);
+        storage.add_thread(&OP { title: "smuggled".to_owned(), body, ..thread_at(4) }).unwrap();
+        assert_eq!(storage.search("oboe", 0, 10).unwrap()[0].snippet, format!("{}oboe{} unclosed", HIGHLIGHT_START, HIGHLIGHT_END));
+
+        // every word has to match
+        assert_eq!(storage.count_search("rust pan").unwrap(), 1);
+        assert_eq!(storage.count_search("\"").unwrap(), 0);
+        assert_eq!(storage.retrieve_search_page("rust", 2, 2).unwrap().items.len(), 1);
+    }
+}
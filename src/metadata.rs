@@ -0,0 +1,391 @@
+use crate::error::{OboeError, Result};
+
+// Uploads are rewritten without the metadata cameras and editors put into them, e.g. EXIF with
+// GPS coordinates and serial numbers, XMP, IPTC and PNG text chunks. Only the parts of the file
+// that hold metadata are left out, the image data is copied as it is, so nothing is re-encoded:
+//     JPEG  APP1 (EXIF, XMP), APP13 (IPTC) and every other APPn segment except JFIF, the ICC
+//           profile and Adobe's colour transform, comments, and anything after the end of the image
+//           such as the second image of MPF files or trailers of phones
+//     PNG   tEXt, zTXt, iTXt, eXIf and tIME chunks
+//     GIF   comments, and application extensions other than the loop count of animations
+//     WebP  EXIF and XMP chunks
+// The content has to be of the format its extension says, see 'upload::validate'.
+
+pub fn strip(content: &[u8], extension: &str) -> Result<Vec<u8>> {
+    match extension {
+        "jpg" => strip_jpeg(content),
+        "png" => strip_png(content),
+        "gif" => strip_gif(content),
+        "webp" => strip_webp(content),
+        _ => Err(malformed()),
+    }
+}
+
+fn malformed() -> OboeError {
+    OboeError::Upload("The uploaded file is not an image that can be read.".to_owned())
+}
+
+// 'length' bytes of 'content' starting at 'start', or an error if it ends before
+fn slice(content: &[u8], start: usize, length: usize) -> Result<&[u8]> {
+    content.get(start..start.checked_add(length).ok_or_else(malformed)?).ok_or_else(malformed)
+}
+
+// Segments are a marker 0xFF 0xXX followed by their length, which counts its own two bytes.
+// The compressed data of a scan follows its SOS segment and is copied up to the next marker.
+// Nothing after EOI is copied.
+fn strip_jpeg(content: &[u8]) -> Result<Vec<u8>> {
+    let mut stripped = slice(content, 0, 2)?.to_vec();
+    let mut position = 2;
+    loop {
+        // any number of 0xFF may pad the space between segments
+        while content.get(position) == Some(&0xff) && content.get(position + 1) == Some(&0xff) {
+            position += 1;
+        }
+        let marker = slice(content, position, 2)?;
+        if marker[0] != 0xff {
+            return Err(malformed());
+        }
+        let kind = marker[1];
+        // SOI, EOI, RSTn and TEM have no length
+        if kind == 0xd8 || kind == 0xd9 || (0xd0..=0xd7).contains(&kind) || kind == 0x01 {
+            stripped.extend_from_slice(marker);
+            position += 2;
+            if kind == 0xd9 {
+                return Ok(stripped);
+            }
+            continue;
+        }
+        let length = slice(content, position + 2, 2)?;
+        let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+        if length < 2 {
+            return Err(malformed());
+        }
+        let segment = slice(content, position, 2 + length)?;
+        let payload = &segment[4..];
+        let metadata = match kind {
+            // APP0, JFIF
+            0xe0 => false,
+            0xe2 => !payload.starts_with(b"ICC_PROFILE\0"),
+            0xee => !payload.starts_with(b"Adobe"),
+            0xe1..=0xef | 0xfe => true,
+            _ => false,
+        };
+        if !metadata {
+            stripped.extend_from_slice(segment);
+        }
+        position += segment.len();
+        // start of scan. Files that end in the middle of it were read by the decoder anyway.
+        if kind == 0xda {
+            let end = scan_end(content, position);
+            stripped.extend_from_slice(&content[position..end]);
+            if end == content.len() {
+                return Ok(stripped);
+            }
+            position = end;
+        }
+    }
+}
+
+// Where the compressed data of a scan starting at 'start' ends, at the next marker or at the end
+// of 'content'. Within the data, 0xFF is followed by 0x00, by a restart marker, or by more 0xFF.
+fn scan_end(content: &[u8], start: usize) -> usize {
+    let mut position = start;
+    while position + 1 < content.len() {
+        let next = content[position + 1];
+        if content[position] == 0xff && next != 0x00 && next != 0xff && !(0xd0..=0xd7).contains(&next) {
+            return position;
+        }
+        position += 1;
+    }
+    content.len()
+}
+
+// After the signature, chunks are their length, a four letter type, the data and a CRC.
+fn strip_png(content: &[u8]) -> Result<Vec<u8>> {
+    let mut stripped = slice(content, 0, 8)?.to_vec();
+    let mut position = 8;
+    while position < content.len() {
+        let header = slice(content, position, 8)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk = slice(content, position, length.checked_add(12).ok_or_else(malformed)?)?;
+        match &header[4..8] {
+            b"tEXt" | b"zTXt" | b"iTXt" | b"eXIf" | b"tIME" => {},
+            _ => stripped.extend_from_slice(chunk),
+        }
+        position += chunk.len();
+    }
+    Ok(stripped)
+}
+
+// the length of the data sub-blocks starting at 'start', including the empty one ending them
+fn gif_sub_blocks(content: &[u8], start: usize) -> Result<usize> {
+    let mut position = start;
+    loop {
+        let size = *content.get(position).ok_or_else(malformed)? as usize;
+        position += 1 + size;
+        if size == 0 {
+            return Ok(position - start);
+        }
+    }
+}
+
+// the length of a colour table if the 'packed' field of a descriptor says there is one
+fn gif_colour_table(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        return 0;
+    }
+    3 << ((packed & 0x07) + 1)
+}
+
+// After the header and the screen descriptor, a GIF is a list of blocks: images, extensions
+// with their data in sub-blocks, and the trailer.
+fn strip_gif(content: &[u8]) -> Result<Vec<u8>> {
+    let screen = slice(content, 0, 13)?;
+    let mut position = 13 + gif_colour_table(screen[10]);
+    let mut stripped = slice(content, 0, position)?.to_vec();
+    loop {
+        match *content.get(position).ok_or_else(malformed)? {
+            // image descriptor, its colour table, the LZW code size and the image data
+            0x2c => {
+                let descriptor = slice(content, position, 10)?;
+                let start = position;
+                position += 10 + gif_colour_table(descriptor[9]) + 1;
+                position += gif_sub_blocks(content, position)?;
+                stripped.extend_from_slice(slice(content, start, position - start)?);
+            },
+            0x21 => {
+                let label = *content.get(position + 1).ok_or_else(malformed)?;
+                let length = 2 + gif_sub_blocks(content, position + 2)?;
+                let extension = slice(content, position, length)?;
+                let metadata = match label {
+                    // comment
+                    0xfe => true,
+                    // application extension, the first sub-block holds its name
+                    0xff => !(extension[2..].starts_with(b"\x0bNETSCAPE2.0") || extension[2..].starts_with(b"\x0bANIMEXTS1.0")),
+                    _ => false,
+                };
+                if !metadata {
+                    stripped.extend_from_slice(extension);
+                }
+                position += length;
+            },
+            // trailer
+            0x3b => {
+                stripped.push(0x3b);
+                return Ok(stripped);
+            },
+            _ => return Err(malformed()),
+        }
+    }
+}
+
+// A RIFF container of chunks, a four letter type, the length of the data, and the data padded to
+// an even length. The flags in the VP8X chunk announcing EXIF and XMP are cleared as well.
+fn strip_webp(content: &[u8]) -> Result<Vec<u8>> {
+    let mut stripped = slice(content, 0, 12)?.to_vec();
+    let mut position = 12;
+    while position < content.len() {
+        let header = slice(content, position, 8)?;
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let padded = length.checked_add(length % 2 + 8).ok_or_else(malformed)?;
+        // the last chunk may go without its padding
+        let chunk = slice(content, position, padded).or_else(|_| slice(content, position, length + 8))?;
+        match &header[0..4] {
+            b"EXIF" | b"XMP " => {},
+            b"VP8X" if length >= 1 => {
+                let flags = stripped.len() + 8;
+                stripped.extend_from_slice(chunk);
+                stripped[flags] &= !(0x08 | 0x04);
+            },
+            _ => stripped.extend_from_slice(chunk),
+        }
+        position += chunk.len();
+    }
+    let size = (stripped.len() - 8) as u32;
+    stripped[4..8].copy_from_slice(&size.to_le_bytes());
+    Ok(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{DynamicImage, GenericImageView, ImageOutputFormat, RgbImage};
+
+    use crate::metadata::strip;
+
+    // Sample files are made from a small encoded image, with metadata put in where cameras and
+    // editors put it. Each carries 'SECRET', which must not be found after stripping.
+    const SECRET: &[u8] = b"GPS 46.4983N 11.3548E serial 0xC0FFEE";
+
+    fn encoded(format: ImageOutputFormat) -> Vec<u8> {
+        let mut content = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 3, image::Rgb([200, 30, 60])))
+            .write_to(&mut content, format).unwrap();
+        content.into_inner()
+    }
+
+    fn contains(content: &[u8], needle: &[u8]) -> bool {
+        content.windows(needle.len()).any(|window| window == needle)
+    }
+
+    fn jpeg_segment(kind: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xff, kind];
+        segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        // the CRC is not checked by the stripping, and the chunks are dropped anyway
+        chunk.extend_from_slice(&[0, 0, 0, 0]);
+        chunk
+    }
+
+    fn gif_extension(label: u8, data: &[&[u8]]) -> Vec<u8> {
+        let mut extension = vec![0x21, label];
+        for block in data {
+            extension.push(block.len() as u8);
+            extension.extend_from_slice(block);
+        }
+        extension.push(0);
+        extension
+    }
+
+    fn webp_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn assert_same_image(original: &[u8], stripped: &[u8]) {
+        let original = image::load_from_memory(original).unwrap();
+        let stripped = image::load_from_memory(stripped).unwrap();
+        assert_eq!(original.dimensions(), stripped.dimensions());
+        assert_eq!(original.to_rgba8().into_raw(), stripped.to_rgba8().into_raw());
+    }
+
+    #[test]
+    fn jpeg() {
+        let plain = encoded(ImageOutputFormat::Jpeg(90));
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend_from_slice(SECRET);
+        let mut xmp = b"http://ns.adobe.com/xap/1.0/\0".to_vec();
+        xmp.extend_from_slice(SECRET);
+        let mut iptc = b"Photoshop 3.0\0".to_vec();
+        iptc.extend_from_slice(SECRET);
+        let mut profile = b"ICC_PROFILE\0".to_vec();
+        profile.extend_from_slice(b"\x01\x01colours");
+
+        let mut sample = plain[..2].to_vec();
+        for segment in &[jpeg_segment(0xe1, &exif), jpeg_segment(0xe1, &xmp), jpeg_segment(0xed, &iptc),
+                         jpeg_segment(0xfe, SECRET), jpeg_segment(0xe2, &profile)] {
+            sample.extend_from_slice(segment);
+        }
+        sample.extend_from_slice(&plain[2..]);
+
+        let stripped = strip(&sample, "jpg").unwrap();
+        assert!(!contains(&stripped, SECRET));
+        assert!(!contains(&stripped, b"Exif"));
+        assert!(contains(&stripped, b"ICC_PROFILE"));
+        assert_same_image(&sample, &stripped);
+        assert_eq!(strip(&plain, "jpg").unwrap(), plain);
+
+        // metadata appended after the end of the image
+        let mut trailing = plain.clone();
+        trailing.extend_from_slice(&jpeg_segment(0xe1, &exif));
+        trailing.extend_from_slice(SECRET);
+        assert_eq!(strip(&trailing, "jpg").unwrap(), plain);
+    }
+
+    #[test]
+    fn progressive_jpeg() {
+        // two scans, with a table between them
+        let mut sample = vec![0xff, 0xd8];
+        sample.extend_from_slice(&jpeg_segment(0xda, &[1, 1, 0, 0, 0x3f, 0]));
+        sample.extend_from_slice(&[0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56]);
+        sample.extend_from_slice(&jpeg_segment(0xc4, &[0x10, 0]));
+        sample.extend_from_slice(&jpeg_segment(0xda, &[1, 1, 0, 1, 0x3f, 0]));
+        sample.extend_from_slice(&[0x78, 0xff, 0xff]);
+        sample.extend_from_slice(&[0xff, 0xd9]);
+        let mut trailing = sample.clone();
+        trailing.extend_from_slice(&jpeg_segment(0xe1, SECRET));
+        assert_eq!(strip(&trailing, "jpg").unwrap(), sample);
+    }
+
+    #[test]
+    fn png() {
+        let plain = encoded(ImageOutputFormat::Png);
+        let mut text = b"Comment\0".to_vec();
+        text.extend_from_slice(SECRET);
+        let mut international = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+        international.extend_from_slice(SECRET);
+
+        // right after IHDR, which is 25 bytes
+        let mut sample = plain[..33].to_vec();
+        for chunk in &[png_chunk(b"tEXt", &text), png_chunk(b"iTXt", &international), png_chunk(b"eXIf", SECRET),
+                       png_chunk(b"zTXt", &text), png_chunk(b"tIME", &[7, 230, 1, 1, 0, 0, 0])] {
+            sample.extend_from_slice(chunk);
+        }
+        sample.extend_from_slice(&plain[33..]);
+
+        let stripped = strip(&sample, "png").unwrap();
+        assert!(!contains(&stripped, SECRET));
+        assert!(!contains(&stripped, b"tIME"));
+        assert_eq!(stripped, plain);
+    }
+
+    #[test]
+    fn gif() {
+        let plain = encoded(ImageOutputFormat::Gif);
+        let screen = 13 + if plain[10] & 0x80 != 0 { 3 << ((plain[10] & 0x07) + 1) } else { 0 };
+        let mut sample = plain[..screen].to_vec();
+        sample.extend_from_slice(&gif_extension(0xff, &[b"NETSCAPE2.0", &[1, 0, 0]]));
+        sample.extend_from_slice(&gif_extension(0xfe, &[SECRET]));
+        sample.extend_from_slice(&gif_extension(0xff, &[b"XMP DataXMP", SECRET]));
+        sample.extend_from_slice(&plain[screen..]);
+
+        let stripped = strip(&sample, "gif").unwrap();
+        assert!(!contains(&stripped, SECRET));
+        assert!(!contains(&stripped, b"XMP DataXMP"));
+        // the loop count of animations is kept
+        assert!(contains(&stripped, b"NETSCAPE2.0"));
+        assert_same_image(&sample, &stripped);
+    }
+
+    #[test]
+    fn webp() {
+        // the image data is copied without being looked at, so any VP8L chunk will do
+        let mut sample = b"RIFF\0\0\0\0WEBP".to_vec();
+        sample.extend_from_slice(&webp_chunk(b"VP8X", &[0x08 | 0x04 | 0x10, 0, 0, 0, 3, 0, 0, 2, 0, 0]));
+        sample.extend_from_slice(&webp_chunk(b"ALPH", &[0; 3]));
+        sample.extend_from_slice(&webp_chunk(b"VP8L", &[0x2f, 1, 2, 3, 4]));
+        sample.extend_from_slice(&webp_chunk(b"EXIF", SECRET));
+        sample.extend_from_slice(&webp_chunk(b"XMP ", SECRET));
+        let size = (sample.len() - 8) as u32;
+        sample[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let stripped = strip(&sample, "webp").unwrap();
+        assert!(!contains(&stripped, SECRET));
+        assert!(!contains(&stripped, b"EXIF"));
+        // only the alpha flag is left, and the size of the container is updated
+        assert_eq!(stripped[20], 0x10);
+        assert_eq!(u32::from_le_bytes([stripped[4], stripped[5], stripped[6], stripped[7]]) as usize, stripped.len() - 8);
+        assert!(contains(&stripped, b"VP8L"));
+    }
+
+    #[test]
+    fn malformed_files() {
+        assert!(strip(b"\xff\xd8\xff\xe1\x00", "jpg").is_err());
+        assert!(strip(b"\x89PNG\r\n\x1a\n\x00\x00\x00\xffIDAT", "png").is_err());
+        assert!(strip(b"GIF89a", "gif").is_err());
+        assert!(strip(b"RIFF\0\0\0\0WEBPVP8L\xff\xff\xff\x00", "webp").is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use postgres::Connection;
+
+// The schema of the DB is versioned. Each migration is a SQL file in 'migrations/' that is
+// compiled into the binary, and the versions already applied are recorded in 'schema_version'.
+// New migrations are appended to 'MIGRATIONS' with the next version number, existing ones must
+// never be edited since they may already be applied somewhere.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "initial", sql: include_str!("../migrations/0001_initial.sql") },
+    Migration { version: 2, name: "created_at", sql: include_str!("../migrations/0002_created_at.sql") },
+    Migration { version: 3, name: "post_count", sql: include_str!("../migrations/0003_post_count.sql") },
+    Migration { version: 4, name: "keys", sql: include_str!("../migrations/0004_keys.sql") },
+    Migration { version: 5, name: "last_bump", sql: include_str!("../migrations/0005_last_bump.sql") },
+    Migration { version: 6, name: "archive", sql: include_str!("../migrations/0006_archive.sql") },
+    Migration { version: 7, name: "boards", sql: include_str!("../migrations/0007_boards.sql") },
+    Migration { version: 8, name: "sticky_locked", sql: include_str!("../migrations/0008_sticky_locked.sql") },
+    Migration { version: 9, name: "search", sql: include_str!("../migrations/0009_search.sql") },
+    Migration { version: 10, name: "post_references", sql: include_str!("../migrations/0010_post_references.sql") },
+    Migration { version: 11, name: "upload_names", sql: include_str!("../migrations/0011_upload_names.sql") },
+    Migration { version: 12, name: "keep_metadata", sql: include_str!("../migrations/0012_keep_metadata.sql") },
+    Migration { version: 13, name: "media", sql: include_str!("../migrations/0013_media.sql") },
+    Migration { version: 14, name: "attachments", sql: include_str!("../migrations/0014_attachments.sql") },
+    Migration { version: 15, name: "spoiler", sql: include_str!("../migrations/0015_spoiler.sql") },
+    Migration { version: 16, name: "discarded_media", sql: include_str!("../migrations/0016_discarded_media.sql") },
+    Migration { version: 17, name: "media_claims", sql: include_str!("../migrations/0017_media_claims.sql") },
+];
+
+const SCHEMA_VERSION_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version integer PRIMARY KEY,
+    name varchar(255) not null,
+    applied_at timestamptz not null default now()
+);";
+
+// the version this binary was built against
+pub fn expected_version() -> i32 {
+    MIGRATIONS.last().map(|migration| migration.version).unwrap_or(0)
+}
+
+// the version the DB is currently at, without modifying anything.
+// DBs set up by hand from the old SQL.md have the tables of migration 1, but no 'schema_version'.
+pub fn current_version(connection: &Connection) -> postgres::Result<i32> {
+    let rows = connection.query("SELECT to_regclass('schema_version') IS NOT NULL, to_regclass('threads') IS NOT NULL", &[])?;
+    let row = rows.get(0);
+    let (has_versions, has_threads): (bool, bool) = (row.get(0), row.get(1));
+    if has_versions {
+        let rows = connection.query("SELECT coalesce(max(version), 0) FROM schema_version", &[])?;
+        return Ok(rows.get(0).get(0));
+    }
+    if has_threads {
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+// applies all pending migrations in order, each in its own transaction.
+// Returns the migrations that were applied.
+pub fn migrate(connection: &Connection) -> postgres::Result<Vec<&'static Migration>> {
+    let current = current_version(connection)?;
+    connection.batch_execute(SCHEMA_VERSION_TABLE)?;
+    if current == 1 {
+        // record the baseline for DBs that predate 'schema_version'
+        connection.execute("INSERT INTO schema_version (version, name) VALUES (1, $1) ON CONFLICT DO NOTHING",
+                           &[&MIGRATIONS[0].name])?;
+    }
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > current) {
+        let transaction = connection.transaction()?;
+        transaction.batch_execute(migration.sql)?;
+        transaction.execute("INSERT INTO schema_version (version, name) VALUES ($1, $2)",
+                            &[&migration.version, &migration.name])?;
+        transaction.commit()?;
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::migrations::{MIGRATIONS, expected_version};
+
+    #[test]
+    fn versions_are_consecutive() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i32 + 1);
+            assert!(!migration.sql.trim().is_empty());
+        }
+        assert_eq!(expected_version(), MIGRATIONS.len() as i32);
+    }
+}
@@ -1,45 +1,47 @@
 use std::fs;
-use rand::Rng;
-use std::error::Error;
 
 // for UNIX time formats
 extern crate chrono;
 // for creating a tripcode
 extern crate md5;
 
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use regex::Regex;
 
-use crate::postgres::{OP, Post};
-use crate::metainfo;
+use crate::config::Settings;
+use crate::error::{OboeError, Result};
+use crate::media::MediaStore;
+use crate::storage::{Storage, thread_archived, thread_locked};
+use crate::postgres::{Board, Media, OP, Post};
+use crate::upload;
 
-use rocket_multipart_form_data::{mime, MultipartFormDataOptions, MultipartFormData, MultipartFormDataField, FileField, TextField};
+use rocket_multipart_form_data::{MultipartFormDataOptions, MultipartFormData, MultipartFormDataError, MultipartFormDataField, FileField, TextField};
 use rocket::Data;
 use rocket::http::ContentType;
-use self::chrono::{Timelike, Datelike};
 
 // retrieves raw data ('data') of the POST request and content types ('ContentType') found in the data.
-// Parses this info, and returns a Opening Post struct ('OP') or nothing if the input is faulty.
-pub fn eval_multipart_thread(content_type: &ContentType, data: Data) -> Option<OP> {
+// Parses this info, and returns a Opening Post struct ('OP') on 'board' or the reason why the input is faulty.
+// The images are stored in 'media' as 'board' and 'settings' say, and claimed in 'storage' until the thread is added.
+pub fn eval_multipart_thread(board: &Board, content_type: &ContentType, data: Data, storage: &dyn Storage, media: &dyn MediaStore, settings: &Settings) -> Result<OP> {
 
     // configuration
     let mut options = MultipartFormDataOptions::new();
     options.allowed_fields.push(MultipartFormDataField::text("poster"));
     options.allowed_fields.push(MultipartFormDataField::text("title"));
     options.allowed_fields.push(MultipartFormDataField::text("body"));
-    options.allowed_fields.push(MultipartFormDataField::file("image").content_type_by_string(Some(mime::IMAGE_STAR)).unwrap());
+    options.allowed_fields.push(MultipartFormDataField::text("spoiler"));
+    options.allowed_fields.push(image_field(settings));
 
     // parsing
     // may fail if the user submits a invalid form, or does not fill out fields correctly.
-    let multipart_form_data = match MultipartFormData::parse(content_type, data, options) {
-        Ok(form) => form,
-        Err(_err) => return None,
-    };
+    let multipart_form_data = parse(content_type, data, options, settings)?;
     // extracting of content of fields
     let poster = multipart_form_data.texts.get(&"poster".to_string());
     let title = multipart_form_data.texts.get(&"title".to_string());
     let body = multipart_form_data.texts.get(&"body".to_string());
     let image = multipart_form_data.files.get(&"image".to_string());
+    // every file input has a checkbox like 'sage' below, sent with the position of its input when ticked
+    let spoilers = extract_positions(multipart_form_data.texts.get(&"spoiler".to_string()));
 
     // evaluation of fields
     let mut poster= extract_text(poster);
@@ -57,52 +59,60 @@ pub fn eval_multipart_thread(content_type: &ContentType, data: Data) -> Option<O
 
     //do not allow DB entry if these two are empty
     if body.is_empty() || title.is_empty() {
-        return None
+        return Err(OboeError::Validation("All fields except the identifier and image have to be filled in.".to_owned()));
     }
 
-    let image = extract_image(image);
-
-    let mut generator = rand::thread_rng();
-    let threadid = generator.gen::<u32>() as i32;
-
-    let (time, date) = get_utc_current();
+    let attachments = extract_images(image, &spoilers, board, false, storage, media, settings)?;
+    let now = Utc::now();
 
-    // see metainfo.rs for more information
-    metainfo::create_info_file(&threadid).unwrap();
-
-    Some(OP {
-        threadid,
+    Ok(OP {
+        // allocated by the storage when the thread is added
+        threadid: 0,
+        board: board.slug.clone(),
         poster,
         title,
         body,
-        img: image,
-        time,
-        date
+        attachments,
+        created_at: now,
+        last_bump: now,
+        post_count: 0,
+        archived_at: None,
+        sticky: false,
+        locked: false
     })
 }
 
 // Essentially the same as above, but without the 'title' field which is needed in a Opening Post, but not in a 'Post'.
-// Additionally, a 'threadid' needs to be specified to signal to which 'Thread' a 'Post' belongs.
-pub fn eval_multipart_post(threadid: i32, content_type: &ContentType, data: Data) -> Option<Post> {
+// Additionally, the opening post ('OP') of the 'Thread' the 'Post' belongs to, and its 'Board', need to be specified.
+// Replies to threads that are locked or archived are refused before anything is parsed or saved.
+pub fn eval_multipart_post(board: &Board, opening: &OP, content_type: &ContentType, data: Data, storage: &dyn Storage, media: &dyn MediaStore, settings: &Settings) -> Result<Post> {
+    if opening.archived_at.is_some() {
+        return Err(thread_archived());
+    }
+    if opening.locked {
+        return Err(thread_locked());
+    }
 
     // configuration
     let mut options = MultipartFormDataOptions::new();
 
     options.allowed_fields.push(MultipartFormDataField::text("poster"));
     options.allowed_fields.push(MultipartFormDataField::text("body"));
-    options.allowed_fields.push(MultipartFormDataField::file("image").content_type_by_string(Some(mime::IMAGE_STAR)).unwrap());
+    options.allowed_fields.push(MultipartFormDataField::text("sage"));
+    options.allowed_fields.push(MultipartFormDataField::text("spoiler"));
+    options.allowed_fields.push(image_field(settings));
 
     // parsing
     // may fail if the user submits a invalid form, or does not fill out fields correctly.
-    let multipart_form_data = match MultipartFormData::parse(content_type, data, options) {
-        Ok(form) => form,
-        Err(_err) => return None,
-    };
+    let multipart_form_data = parse(content_type, data, options, settings)?;
 
     // extracting of content of fields
     let poster = multipart_form_data.texts.get(&"poster".to_string());
     let body = multipart_form_data.texts.get(&"body".to_string());
     let img = multipart_form_data.files.get(&"image".to_string());
+    // an unchecked checkbox is not submitted at all
+    let sage = !extract_text(multipart_form_data.texts.get(&"sage".to_string())).is_empty();
+    let spoilers = extract_positions(multipart_form_data.texts.get(&"spoiler".to_string()));
 
     //evaluation of fields
     let mut poster= extract_text(poster);
@@ -117,35 +127,36 @@ pub fn eval_multipart_post(threadid: i32, content_type: &ContentType, data: Data
 
     //do not allow DB entry if body is empty
     if body.is_empty() {
-        return None
+        return Err(OboeError::Validation("A comment needs some content.".to_owned()));
     }
 
-    let img = extract_image(img);
-
-    let (time, date) = get_utc_current();
-
-    // see metainfo.rs for more information
-    let postid = metainfo::get_postid(&threadid, true).unwrap();
+    let attachments = extract_images(img, &spoilers, board, true, storage, media, settings)?;
 
-    Some(Post {
-        threadid,
+    Ok(Post {
+        threadid: opening.threadid,
         poster,
         body,
-        img,
-        time,
-        date,
-        postid
+        attachments,
+        created_at: Utc::now(),
+        // allocated by the storage when the post is added
+        postid: 0,
+        sage
     })
 }
 
 
+// The content type the client sends for the image is not checked, 'upload::validate' looks at the
+// file itself. Only the size of each file is limited while it is received.
+fn image_field(settings: &Settings) -> MultipartFormDataField<'static> {
+    MultipartFormDataField::file("image").size_limit(settings.max_upload_bytes)
+}
 
-// gets current time and date, formatted as a tuple of strings.
-fn get_utc_current() -> (String, String) {
-    let now: DateTime<Utc> = Utc::now();
-    let time = format!("{}:{}:{}", now.hour() + 2, now.minute(), now.second());
-    let date = format!("{}.{}.{}", now.day(), now.month(), now.year());
-    (time, date)
+// parses the form, telling posters how big their images may be if they are too big
+fn parse(content_type: &ContentType, data: Data, options: MultipartFormDataOptions, settings: &Settings) -> Result<MultipartFormData> {
+    MultipartFormData::parse(content_type, data, options).map_err(|err| match err {
+        MultipartFormDataError::DataTooLargeError(ref field) if &**field == "image" => upload::too_large(settings),
+        err => err.into(),
+    })
 }
 
 // computes a tripcode. Everything after the first '#' is hashed with md5
@@ -160,6 +171,7 @@ fn get_tripcode(poster: String) -> String {
 
 // helper functions for the eval_* functions above. These are needed since the multipart libary used
 // uses structs that are far to abstract for our need.
+// A field that was not submitted counts as empty.
 fn extract_text(text_field: Option<&TextField>) -> String {
     if let Some(text_field) = text_field {
         match text_field {
@@ -174,55 +186,77 @@ fn extract_text(text_field: Option<&TextField>) -> String {
             }
         }
     }
-    "error parsing this textfield".to_owned()
+    String::new()
 }
 
-fn extract_image(image_field: Option<&FileField>) -> String {
-    if let Some(image_field) = image_field {
-        match image_field {
-            FileField::Single(file) => {
-                let _content_type = &file.content_type;
-                let _file_name = file.file_name.clone();
-                let _path = &file.path;
-                let mut pathbuilder: String = String::from("Pictures/");
-                pathbuilder.push_str(_file_name.unwrap().as_str());
-                println!(
-                    "Saving to accessable directory {} ...", &pathbuilder
-                );
-                match fs::copy(_path, &pathbuilder) {
-                    Ok(_) => { /* Ok */},
-                    Err(err) => {
-                        println!(
-                            "Error: {}", err.description()
-                        );
-
-                    },
-                }
-                let pathbuilder = pathbuilder.clone();
-                return pathbuilder
-            }
-            FileField::Multiple(_file) => {
-                //unused, but needs to be specified in this interface
-                //could be used for multiple file uploads if multiple html forms are present
-            }
+// the positions sent by a checkbox that is repeated for every file input, see 'extract_images'.
+// Values that are not a position are ignored.
+fn extract_positions(text_field: Option<&TextField>) -> Vec<usize> {
+    let texts = match text_field {
+        Some(TextField::Single(text)) => vec![text],
+        Some(TextField::Multiple(texts)) => texts.iter().collect(),
+        None => Vec::new(),
+    };
+    texts.iter().filter_map(|text| text.text.trim().parse().ok()).collect()
+}
+
+// stores the uploaded images, see 'upload::prepare' and 'upload::save', in the order they were sent.
+// Every file input of the form is a part of its own named "image", sent empty if no file was chosen.
+// Posts without an image have no attachments.
+// A thread, or a 'reply', can have as many images as its board allows. Nothing is stored if there are
+// more, or if any of them is refused. The images whose input is at one of the 'spoilers' positions,
+// counting from 0 and including empty inputs, are spoilers.
+fn extract_images(image_field: Option<&FileField>, spoilers: &[usize], board: &Board, reply: bool, storage: &dyn Storage, store: &dyn MediaStore, settings: &Settings) -> Result<Vec<Media>> {
+    let files = match image_field {
+        Some(FileField::Single(file)) => vec![file],
+        Some(FileField::Multiple(files)) => files.iter().collect(),
+        None => Vec::new(),
+    };
+    let mut chosen = Vec::new();
+    for (position, file) in files.into_iter().enumerate() {
+        if fs::metadata(&file.path)?.len() > 0 {
+            chosen.push((file, spoilers.contains(&position)));
+        }
+    }
+    let max_attachments = board.max_attachments(reply, settings);
+    if chosen.len() as i32 > max_attachments {
+        let post = if reply { "a reply" } else { "a thread" };
+        return Err(OboeError::Validation(if max_attachments > 0 {
+            format!("At most {} images can be attached to {} on /{}/.", max_attachments, post, board.slug)
+        } else {
+            format!("Images can't be attached to {} on /{}/.", post, board.slug)
+        }));
+    }
+
+    let mut uploads = Vec::new();
+    for (file, spoiler) in chosen {
+        let file_name = file.file_name.as_ref()
+            .ok_or_else(|| OboeError::Upload("The uploaded image has no file name.".to_owned()))?;
+        uploads.push((upload::prepare(&file.path, upload::display_name(file_name), board.keep_metadata, settings)?, spoiler));
+    }
+
+    let mut attachments = Vec::new();
+    for (prepared, spoiler) in uploads {
+        match upload::save(prepared, storage, store) {
+            Ok(media) => attachments.push(Media { spoiler, ..media }),
+            Err(err) => {
+                upload::abandon(&attachments, storage, store)?;
+                return Err(err);
+            },
         }
     }
-    "error parsing this image".to_owned()
+    Ok(attachments)
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::{DateTime, Utc};
-    use super::chrono::{Timelike, Datelike};
+    use super::get_tripcode;
 
     #[test]
-    fn timeformats() {
-        let now: DateTime<Utc> = Utc::now();
-        //UNIX, not UTC or GMT, but has been adjusted for GMT+1
-        let time = format!("{}:{}:{}", now.hour() + 2, now.minute(), now.second());
-        let date = format!("{}/{}/{}", now.day(), now.month(), now.year());
-        //check manually against your system clock
-        assert_eq!(time, time);
-        assert_eq!(date, date);
+    fn tripcodes() {
+        // md5 of "secret"
+        assert_eq!(get_tripcode("user#secret".to_owned()), "user#5ebe2294ecd0e0f08eab7690d2a6ee69");
+        // an empty secret does not produce a tripcode
+        assert_eq!(get_tripcode("user#".to_owned()), "");
     }
-}
\ No newline at end of file
+}
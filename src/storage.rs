@@ -0,0 +1,282 @@
+use std::process;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+
+use crate::config::Settings;
+use crate::error::{OboeError, Result};
+use crate::markup;
+use crate::postgres::{Backlinks, Board, Media, OP, Post, SearchHit, Thread, PostgresStorage, get_psql_entry};
+use crate::memory::MemoryStorage;
+
+// Every read and write the routes perform goes through a 'Storage'. Rocket keeps one boxed
+// instance in its managed state, which is why implementations must be shareable between threads.
+pub trait Storage: Send + Sync {
+    // Adds a new 'Board'. Fails with 'OboeError::Validation' if its slug is not allowed or already taken.
+    fn add_board(&self, board: &Board) -> Result<()>;
+
+    // all boards, ordered by their slug
+    fn retrieve_boards(&self) -> Result<Vec<Board>>;
+
+    // whether images uploaded to the board 'slug' keep their metadata. Fails with 'OboeError::NotFound'
+    // if the board does not exist.
+    fn set_keep_metadata(&self, slug: &str, keep_metadata: bool) -> Result<()>;
+
+    // how many images new threads and replies on the board 'slug' can have, 'None' uses the settings.
+    // Fails with 'OboeError::NotFound' if the board does not exist.
+    fn set_max_attachments(&self, slug: &str, thread: Option<i32>, reply: Option<i32>) -> Result<()>;
+
+    // Adds a filled-in 'OP' to the storage. The 'threadid' of 'op' is ignored, instead a free
+    // thread id is allocated together with the insertion and returned. Thread ids are unique over
+    // all boards, the board of 'op' has to exist.
+    fn add_thread(&self, op: &OP) -> Result<i32>;
+
+    // Adds a filled-in 'Post' to the storage. The 'postid' of 'post' is ignored, instead the next
+    // free number of its thread is allocated together with the insertion and returned.
+    // Unless the post is saged or the thread already has as many replies as the bump limit of its board,
+    // the thread is bumped.
+    // Fails with 'OboeError::NotFound' if the thread does not exist, and with 'OboeError::Forbidden'
+    // if it is archived or locked.
+    fn add_post(&self, post: &Post) -> Result<i32>;
+
+    // retrieve a opening post ('OP').
+    fn retrieve_op(&self, threadid: &i32) -> Result<Option<OP>>;
+
+    // retrieve at most 'limit' opening posts ('OP') of 'board', skipping the first 'offset'. Either of live
+    // threads, stickies and then the most recently bumped first, or of 'archived' threads, the most recently
+    // archived first.
+    fn retrieve_op_range(&self, board: &str, archived: bool, offset: i64, limit: i64) -> Result<Vec<OP>>;
+
+    // the number of live or 'archived' threads on 'board'
+    fn count_threads(&self, board: &str, archived: bool) -> Result<i64>;
+
+    // archives the least recently bumped live threads of 'board' until at most 'max_threads' are left.
+    // Stickies are never archived, and do not count towards 'max_threads'.
+    fn archive_overflow(&self, board: &str, max_threads: i64) -> Result<()>;
+
+    // pins a thread to the top of the index of its board, or unpins it. Fails with 'OboeError::NotFound'
+    // if the thread does not exist.
+    fn set_sticky(&self, threadid: &i32, sticky: bool) -> Result<()>;
+
+    // closes a thread for replies, or opens it again. Fails with 'OboeError::NotFound' if the thread
+    // does not exist.
+    fn set_locked(&self, threadid: &i32, locked: bool) -> Result<()>;
+
+    // deletes threads of any board archived before 'before' together with their posts, and returns them.
+    // The files of their images are recorded as discarded in the same step, see 'discarded_media'.
+    fn purge_archived(&self, before: DateTime<Utc>) -> Result<Vec<Thread>>;
+
+    // the images of purged threads whose files were not deleted yet, as the path of the image and of
+    // its thumbnail. See 'archive::prune'.
+    fn discarded_media(&self) -> Result<Vec<(String, String)>>;
+
+    // takes the image at 'path' off the discarded ones, once its files are deleted or in use again
+    fn forget_discarded(&self, path: &str) -> Result<()>;
+
+    // retrieve at most 'limit' opening posts and posts of any board, archived or not, matching 'query',
+    // the best matches first, skipping the first 'offset'.
+    fn search(&self, query: &str, offset: i64, limit: i64) -> Result<Vec<SearchHit>>;
+
+    // the number of opening posts and posts matching 'query'
+    fn count_search(&self, query: &str) -> Result<i64>;
+
+    // retrieve all posts ('Post') belonging to a opening post ('OP') in an array if there exist some.
+    fn retrieve_posts(&self, threadid: &i32) -> Result<Option<Vec<Post>>>;
+
+    // which posts of a thread quote which, see 'quoted_posts'
+    fn retrieve_backlinks(&self, threadid: &i32) -> Result<Backlinks>;
+
+    // retrieve only the last 'count' posts ('Post') of a thread, still in the order they were made.
+    fn retrieve_last_posts(&self, threadid: &i32, count: i64) -> Result<Option<Vec<Post>>>;
+
+    // retrieve at most 'limit' images of both opening posts and posts on 'board', the newest first, skipping the first 'offset'.
+    // Every attachment is an image of its own, those of one post are in the order they were attached.
+    fn retrieve_image_range(&self, board: &str, offset: i64, limit: i64) -> Result<Vec<Media>>;
+
+    // the number of images over both opening posts and posts on 'board'
+    fn count_images(&self, board: &str) -> Result<i64>;
+
+    // Claims the image at 'path' for a post that is about to be added, before its files are stored, see
+    // 'upload::save'. Adding an opening post or post releases one claim of each of its attachments,
+    // 'release_image' those of a post that was not added.
+    fn claim_image(&self, path: &str) -> Result<()>;
+
+    fn release_image(&self, path: &str) -> Result<()>;
+
+    // Calls 'remove' to delete the files of the image at 'path', unless a 'Media' of an opening post or
+    // post, archived or not, has it or it is claimed. Identical uploads share their files, see upload.rs.
+    // Claims of the image wait until 'remove' returned.
+    fn remove_unused_image(&self, path: &str, remove: &mut dyn FnMut() -> Result<()>) -> Result<()>;
+
+    // Retrieval of a single 'Board', or 'OboeError::NotFound' if it does not exist
+    fn retrieve_board(&self, slug: &str) -> Result<Board> {
+        Ok(find_board(&self.retrieve_boards()?, slug)?.clone())
+    }
+
+    // Retrieval of an 'OP', or 'OboeError::NotFound' if it does not exist or was posted on another board
+    fn retrieve_op_on(&self, board: &str, threadid: &i32) -> Result<OP> {
+        self.retrieve_op(threadid)?
+            .filter(|op| op.board == board)
+            .ok_or_else(thread_not_found)
+    }
+
+    // Retrieval of a 'Thread', or 'OboeError::NotFound' if it does not exist
+    fn retrieve_thread(&self, threadid: &i32) -> Result<Thread> {
+        let op = self.retrieve_op(threadid)?.ok_or_else(thread_not_found)?;
+        // iff there exists a thread with said threadid, then posts may also exist.
+        let posts = self.retrieve_posts(threadid)?;
+        Ok(Thread { op, posts })
+    }
+
+    // same as above, but only with the last 'count' posts
+    fn retrieve_thread_tail(&self, threadid: &i32, count: i64) -> Result<Thread> {
+        let op = self.retrieve_op(threadid)?.ok_or_else(thread_not_found)?;
+        let posts = self.retrieve_last_posts(threadid, count)?;
+        Ok(Thread { op, posts })
+    }
+
+    // a page of the index or the archive of 'board', see 'Page'
+    fn retrieve_index_page(&self, board: &str, archived: bool, page: u32, per_page: u32) -> Result<Page<OP>> {
+        let (offset, pages) = Page::<OP>::locate(page, per_page, self.count_threads(board, archived)?)?;
+        let items = self.retrieve_op_range(board, archived, offset, per_page as i64)?;
+        Ok(Page { items, page, pages })
+    }
+
+    // a page of search results, see 'Page'
+    fn retrieve_search_page(&self, query: &str, page: u32, per_page: u32) -> Result<Page<SearchHit>> {
+        let (offset, pages) = Page::<SearchHit>::locate(page, per_page, self.count_search(query)?)?;
+        let items = self.search(query, offset, per_page as i64)?;
+        Ok(Page { items, page, pages })
+    }
+
+    // a page of the gallery of 'board', see 'Page'
+    fn retrieve_gallery_page(&self, board: &str, page: u32, per_page: u32) -> Result<Page<Media>> {
+        let (offset, pages) = Page::<Media>::locate(page, per_page, self.count_images(board)?)?;
+        let items = self.retrieve_image_range(board, offset, per_page as i64)?;
+        Ok(Page { items, page, pages })
+    }
+}
+
+// One page out of a longer list, pages are counted from 1.
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: u32,
+    // the number of pages there are in total, at least 1 even if the list is empty
+    pub pages: u32
+}
+
+impl<T> Page<T> {
+    // the offset of the first item on 'page' and the total number of pages for 'total' items.
+    // Pages past the last one do not exist.
+    pub fn locate(page: u32, per_page: u32, total: i64) -> Result<(i64, u32)> {
+        let per_page = per_page.max(1) as i64;
+        let pages = ((total + per_page - 1) / per_page).max(1) as u32;
+        if page == 0 || page > pages {
+            return Err(OboeError::NotFound(format!("There is no page {}, the last one is {}.", page, pages)));
+        }
+        Ok(((page as i64 - 1) * per_page, pages))
+    }
+}
+
+// The backend is chosen by the 'storage' key of the rocket config (Rocket.toml or ROCKET_STORAGE).
+// Postgres is the default, "memory" keeps everything in RAM and is lost on shutdown.
+pub fn from_settings(settings: &Settings) -> Box<dyn Storage> {
+    match settings.storage.as_str() {
+        "postgres" => {
+            let storage = PostgresStorage::new(&get_psql_entry(), settings);
+            storage.check_schema();
+            Box::new(storage)
+        },
+        "memory" => Box::new(MemoryStorage::new(settings)),
+        other => {
+            println!("Unknown storage backend \"{}\", use either \"postgres\" or \"memory\".", other);
+            process::exit(1);
+        },
+    }
+}
+
+// the board existing threads were moved into by the migration to multiple boards
+pub const DEFAULT_BOARD: &str = "b";
+
+// Slugs are used as the first segment of URLs, next to the routes that do not belong to any board.
+// They are kept short and plain, and may not shadow those routes.
+const RESERVED_SLUGS: &[&str] = &["static", "thread", "search", "form", "404", "500"];
+
+pub fn check_slug(slug: &str) -> Result<()> {
+    let plain = !slug.is_empty() && slug.len() <= 16
+        && slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    if !plain || RESERVED_SLUGS.contains(&slug) {
+        return Err(OboeError::Validation(format!("\"{}\" can't be used as a board, use up to 16 lowercase letters and digits.", slug)));
+    }
+    Ok(())
+}
+
+// the board with 'slug' out of a list of boards
+pub fn find_board<'a>(boards: &'a [Board], slug: &str) -> Result<&'a Board> {
+    boards.iter()
+        .find(|board| board.slug == slug)
+        .ok_or_else(|| OboeError::NotFound(format!("There is no board /{}/.", slug)))
+}
+
+// the words matched by a search are marked with these in 'SearchHit::snippet'. Control characters
+// have no meaning in a post, unlike any markup the snippet could be given here. Posters can still
+// send them, so they are taken out of bodies before the snippet is made, see 'without_highlights'.
+pub const HIGHLIGHT_START: char = '\u{2}';
+pub const HIGHLIGHT_END: char = '\u{3}';
+
+// 'text' without the highlight markers
+pub fn without_highlights(text: &str) -> String {
+    text.chars().filter(|c| *c != HIGHLIGHT_START && *c != HIGHLIGHT_END).collect()
+}
+
+// the posts of its thread that the post 'postid' with 'body' quotes with '>>123'. Only earlier
+// posts count, a post can't quote one that does not exist yet.
+pub fn quoted_posts(body: &str, postid: i32) -> Vec<i32> {
+    markup::quoted_posts(body).into_iter()
+        .filter(|quoted| *quoted < postid)
+        .collect()
+}
+
+// a candidate for a thread id when 'random_thread_ids' is set. Always positive, the caller
+// has to retry if it is already taken.
+pub fn random_threadid() -> i32 {
+    rand::thread_rng().gen_range(1, i32::max_value())
+}
+
+pub fn thread_not_found() -> OboeError {
+    OboeError::NotFound("The specified thread does not exist.".to_owned())
+}
+
+pub fn thread_archived() -> OboeError {
+    OboeError::Forbidden("This thread is archived and can no longer be replied to.".to_owned())
+}
+
+pub fn thread_locked() -> OboeError {
+    OboeError::Forbidden("This thread is locked and can't be replied to.".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::postgres::OP;
+    use crate::storage::{Page, check_slug};
+
+    #[test]
+    fn page_offsets() {
+        assert_eq!(Page::<OP>::locate(1, 10, 0).unwrap(), (0, 1));
+        assert_eq!(Page::<OP>::locate(1, 10, 10).unwrap(), (0, 1));
+        assert_eq!(Page::<OP>::locate(2, 10, 11).unwrap(), (10, 2));
+        assert!(Page::<OP>::locate(0, 10, 11).is_err());
+        assert!(Page::<OP>::locate(3, 10, 11).is_err());
+    }
+
+    #[test]
+    fn board_slugs() {
+        assert!(check_slug("b").is_ok());
+        assert!(check_slug("g2").is_ok());
+        assert!(check_slug("").is_err());
+        assert!(check_slug("B").is_err());
+        assert!(check_slug("b/c").is_err());
+        assert!(check_slug("static").is_err());
+        assert!(check_slug("averyveryverylongslug").is_err());
+    }
+}
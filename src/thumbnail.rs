@@ -0,0 +1,116 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use image::{DynamicImage, GenericImageView, ImageError, ImageFormat};
+
+use crate::error::{OboeError, Result};
+
+// Thumbnails are stored next to their original, 'Pictures/cat.png' has 'Pictures/cat.thumb.png'.
+// They are made when an image is uploaded, older files get theirs with 'oboe thumbnails'.
+// WebP images can only be read, their thumbnails are PNGs.
+
+const SUFFIX: &str = ".thumb";
+
+// the extensions of the images that are served from 'Pictures/', and so get thumbnails
+pub const EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "webp"];
+
+// where the thumbnail of the image at 'original' is kept
+pub fn thumbnail_path(original: &str) -> String {
+    let path = Path::new(original);
+    let stem = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(stem) => stem,
+        None => return original.to_owned(),
+    };
+    let name = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("webp") => format!("{}{}.png", stem, SUFFIX),
+        Some(extension) => format!("{}{}.{}", stem, SUFFIX, extension),
+        None => format!("{}{}", stem, SUFFIX),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+pub fn is_thumbnail(path: &str) -> bool {
+    Path::new(path).file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(|stem| stem.ends_with(SUFFIX))
+        .unwrap_or(false)
+}
+
+// Scales the image at 'original' down to fit into 'max_width' x 'max_height', keeping its aspect
+// ratio, and saves it at 'thumbnail_path(original)'. Images that already fit are saved as they are.
+pub fn generate(original: &str, max_width: u32, max_height: u32) -> Result<String> {
+    let image = image::open(original).map_err(unreadable)?;
+    save(&image, original, max_width, max_height)
+}
+
+// the same, for an 'image' already decoded from the file at 'original'
+pub fn save(image: &DynamicImage, original: &str, max_width: u32, max_height: u32) -> Result<String> {
+    let path = thumbnail_path(original);
+    fs::write(&path, encode(image, original, max_width, max_height)?)?;
+    Ok(path)
+}
+
+// the content of the thumbnail of 'image', in the format 'thumbnail_path(original)' has, for
+// uploads that are kept in a 'MediaStore' rather than a directory
+pub fn encode(image: &DynamicImage, original: &str, max_width: u32, max_height: u32) -> Result<Vec<u8>> {
+    let format = ImageFormat::from_path(thumbnail_path(original)).map_err(unreadable)?;
+    let mut content = Cursor::new(Vec::new());
+    if image.width() > max_width || image.height() > max_height {
+        image.thumbnail(max_width, max_height).write_to(&mut content, format).map_err(unreadable)?;
+    } else {
+        image.write_to(&mut content, format).map_err(unreadable)?;
+    }
+    Ok(content.into_inner())
+}
+
+fn unreadable(err: ImageError) -> OboeError {
+    match err {
+        ImageError::IoError(err) => OboeError::Io(err),
+        _ => OboeError::Upload("The uploaded file is not an image that can be read.".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use image::{DynamicImage, GenericImageView, RgbImage};
+
+    use crate::error::OboeError;
+    use crate::thumbnail::{generate, is_thumbnail, thumbnail_path};
+
+    #[test]
+    fn paths() {
+        assert_eq!(thumbnail_path("Pictures/cat.png"), "Pictures/cat.thumb.png");
+        assert_eq!(thumbnail_path("Pictures/archive.tar.gz"), "Pictures/archive.tar.thumb.gz");
+        assert_eq!(thumbnail_path("Pictures/cat"), "Pictures/cat.thumb");
+        assert_eq!(thumbnail_path("Pictures/cat.webp"), "Pictures/cat.thumb.png");
+        assert!(is_thumbnail("Pictures/cat.thumb.png"));
+        assert!(!is_thumbnail("Pictures/cat.png"));
+    }
+
+    #[test]
+    fn scaled_to_fit() {
+        let directory = env::temp_dir().join(format!("oboe-thumbnails-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let wide = directory.join("wide.png").to_string_lossy().into_owned();
+        let small = directory.join("small.png").to_string_lossy().into_owned();
+        DynamicImage::ImageRgb8(RgbImage::new(600, 300)).save(&wide).unwrap();
+        DynamicImage::ImageRgb8(RgbImage::new(40, 20)).save(&small).unwrap();
+
+        let thumbnail = generate(&wide, 250, 250).unwrap();
+        assert_eq!(image::open(&thumbnail).unwrap().dimensions(), (250, 125));
+        // never scaled up
+        let thumbnail = generate(&small, 250, 250).unwrap();
+        assert_eq!(image::open(&thumbnail).unwrap().dimensions(), (40, 20));
+
+        let text = directory.join("text.png").to_string_lossy().into_owned();
+        fs::write(&text, "not an image").unwrap();
+        match generate(&text, 250, 250) {
+            Err(OboeError::Upload(_)) => {},
+            _ => panic!("a text file was accepted as an image"),
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,347 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::slice;
+
+use image::{DynamicImage, GenericImageView, ImageError, ImageFormat};
+use image::io::Reader;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use crate::config::Settings;
+use crate::error::{OboeError, Result};
+use crate::media::MediaStore;
+use crate::metadata;
+use crate::postgres::Media;
+use crate::storage::Storage;
+use crate::thumbnail;
+
+// Uploads are kept in 'Pictures/' under the SHA-256 of their content and an extension that comes
+// from the content as well, e.g. 'Pictures/9f86d0…0a08.png'. The name the poster's file had is
+// only shown next to the image. Two uploads of the same image share one file, and nothing a
+// client sends ends up in a path.
+// What a client claims about its file is not trusted either: uploads are recognized by their first
+// bytes, and are decoded in full before they are stored, within the limits of 'Settings'.
+// WebP is not accepted, the decoder of the 'image' crate only reads lossy images without animation.
+
+pub const DIRECTORY: &str = "Pictures";
+
+lazy_static! {
+    // the names 'pictures' serves, of stored images and their thumbnails
+    static ref STORED_NAME: Regex = Regex::new(r"^[0-9a-f]{64}(\.thumb)?\.(png|jpg|gif|webp)$").unwrap();
+}
+
+// An upload that passed 'validate', with its metadata stripped and its thumbnail made, that is
+// not stored yet. Posts with several images have all of them prepared before any is stored.
+pub struct Upload {
+    media: Media,
+    content: Vec<u8>,
+    thumbnail: Vec<u8>
+}
+
+// Reads the image at 'temporary', where the multipart parser left it, and makes its thumbnail as set
+// in 'settings'. Its metadata is stripped unless 'keep_metadata' is set, see metadata.rs. The 'Media'
+// describing it is uploaded as 'original_name'.
+pub fn prepare(temporary: &Path, original_name: String, keep_metadata: bool, settings: &Settings) -> Result<Upload> {
+    let mut content = fs::read(temporary)?;
+    let (image, extension) = validate(&content, settings)?;
+    if !keep_metadata {
+        content = metadata::strip(&content, extension)?;
+    }
+    let hash = content_hash(&content);
+    let path = format!("{}/{}.{}", DIRECTORY, hash, extension);
+    let thumbnail = thumbnail::encode(&image, &path, settings.thumbnail_width, settings.thumbnail_height)?;
+    let media = Media {
+        // allocated by the storage
+        id: 0,
+        hash,
+        mime: mime(extension).to_owned(),
+        width: image.width() as i32,
+        height: image.height() as i32,
+        byte_size: content.len() as i64,
+        original_name,
+        thumbnail: thumbnail::thumbnail_path(&path),
+        path,
+        // up to the poster, see 'multipart::extract_images'
+        spoiler: false
+    };
+    Ok(Upload { media, content, thumbnail })
+}
+
+// Claims 'upload' in 'storage' and puts it and its thumbnail into 'media', then returns the 'Media'
+// describing it. If the same image was uploaded before, the files that are already there are used.
+// The claim keeps other posts from deleting those files until the post it is attached to is added,
+// or 'abandon' is called for it if that fails. See 'Storage::claim_image'.
+pub fn save(upload: Upload, storage: &dyn Storage, media: &dyn MediaStore) -> Result<Media> {
+    storage.claim_image(&upload.media.path)?;
+    let files = [(&upload.media.path, &upload.content), (&upload.media.thumbnail, &upload.thumbnail)];
+    for (path, content) in files.iter() {
+        let name = name_of(path);
+        let stored = match media.exists(name) {
+            Ok(true) => Ok(()),
+            Ok(false) => media.put(name, content, mime(extension_of(path))),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = stored {
+            abandon(slice::from_ref(&upload.media), storage, media)?;
+            return Err(err);
+        }
+    }
+    Ok(upload.media)
+}
+
+// 'prepare' and 'save' for a single image, which is claimed like with 'save'. Nothing is kept if it fails.
+pub fn store(temporary: &Path, original_name: String, keep_metadata: bool, storage: &dyn Storage, media: &dyn MediaStore, settings: &Settings) -> Result<Media> {
+    save(prepare(temporary, original_name, keep_metadata, settings)?, storage, media)
+}
+
+// Releases the claims 'save' made for 'attachments' of a post that could not be added, and deletes
+// their files with 'discard' if nothing else refers to them.
+pub fn abandon(attachments: &[Media], storage: &dyn Storage, media: &dyn MediaStore) -> Result<()> {
+    for attachment in attachments {
+        storage.release_image(&attachment.path)?;
+    }
+    let mut attachments: Vec<&Media> = attachments.iter().collect();
+    attachments.sort_by(|a, b| a.path.cmp(&b.path));
+    attachments.dedup_by(|a, b| a.path == b.path);
+    for attachment in attachments {
+        discard(&attachment.path, &attachment.thumbnail, storage, media)?;
+    }
+    Ok(())
+}
+
+// Deletes the image at 'path' and its 'thumbnail', unless a thread or post has it or it is claimed,
+// e.g. after its thread was purged. Files other posts were given as well are kept.
+pub fn discard(path: &str, thumbnail: &str, storage: &dyn Storage, media: &dyn MediaStore) -> Result<()> {
+    // only ever delete what was stored in 'Pictures/', whatever name the client uploaded the image with
+    let deletable = |path: &str| path.starts_with("Pictures/") && !path.contains("..");
+    storage.remove_unused_image(path, &mut || {
+        for file in [path, thumbnail].iter().filter(|file| deletable(file)) {
+            media.delete(name_of(file))?;
+        }
+        Ok(())
+    })
+}
+
+// Checks that 'content' is an image of a format that can be uploaded and within the limits of
+// 'settings', and decodes it. Returns the image and the extension it is stored with.
+// Exceeding the byte size is 'OboeError::TooLarge', anything else 'OboeError::Upload'.
+pub fn validate(content: &[u8], settings: &Settings) -> Result<(DynamicImage, &'static str)> {
+    if content.len() as u64 > settings.max_upload_bytes {
+        return Err(too_large(settings));
+    }
+    let (format, extension) = sniff(content)
+        .ok_or_else(|| OboeError::Upload("Only PNG, JPEG and GIF images can be uploaded.".to_owned()))?;
+    // the header is read first, so huge images are refused before memory is allocated for their pixels
+    let (width, height) = Reader::with_format(Cursor::new(content), format)
+        .into_dimensions()
+        .map_err(unreadable)?;
+    if width > settings.max_image_width || height > settings.max_image_height {
+        return Err(OboeError::Upload(format!("Images can be at most {} x {} pixels, this one is {} x {}.",
+                                             settings.max_image_width, settings.max_image_height, width, height)));
+    }
+    if width as u64 * height as u64 > settings.max_image_pixels {
+        return Err(OboeError::Upload(format!("Images can have at most {} pixels in total, this one has {}.",
+                                             settings.max_image_pixels, width as u64 * height as u64)));
+    }
+    let image = image::load_from_memory_with_format(content, format).map_err(unreadable)?;
+    Ok((image, extension))
+}
+
+pub fn too_large(settings: &Settings) -> OboeError {
+    OboeError::TooLarge(format!("Images can be at most {} KiB.", settings.max_upload_bytes / 1024))
+}
+
+// the content is in memory, a failed read means it ends too early
+fn unreadable(_: ImageError) -> OboeError {
+    OboeError::Upload("The uploaded file is not an image that can be read.".to_owned())
+}
+
+// the format of an upload, by its magic bytes
+fn sniff(content: &[u8]) -> Option<(ImageFormat, &'static str)> {
+    if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some((ImageFormat::Png, "png"))
+    } else if content.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some((ImageFormat::Jpeg, "jpg"))
+    } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+        Some((ImageFormat::Gif, "gif"))
+    } else {
+        None
+    }
+}
+
+// the name of an image with 'content' without its extension
+pub fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+fn mime(extension: &str) -> &'static str {
+    match extension {
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+// the name a file is kept under in a 'MediaStore', 'cat.png' for 'Pictures/cat.png'
+pub fn name_of(path: &str) -> &str {
+    let prefix = format!("{}/", DIRECTORY);
+    if path.starts_with(&prefix) { &path[prefix.len()..] } else { path }
+}
+
+fn extension_of(path: &str) -> &str {
+    path.rsplit('.').next().unwrap_or("")
+}
+
+pub fn is_stored_name(name: &str) -> bool {
+    STORED_NAME.is_match(name)
+}
+
+// the file name the poster uploaded, as it is shown. Directories some browsers send along are left out.
+pub fn display_name(file_name: &str) -> String {
+    file_name.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("").to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::io::Cursor;
+    use std::slice;
+
+    use image::{DynamicImage, GenericImageView, ImageOutputFormat, RgbImage};
+
+    use crate::config::Settings;
+    use crate::error::OboeError;
+    use crate::media::{LocalStore, MediaStore};
+    use crate::memory::MemoryStorage;
+    use crate::upload::{abandon, content_hash, display_name, is_stored_name, name_of, prepare, save, validate};
+
+    fn encoded(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let mut content = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height)).write_to(&mut content, format).unwrap();
+        content.into_inner()
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        encoded(width, height, ImageOutputFormat::Png)
+    }
+
+    // a RIFF container with 'chunks', as WebP files are
+    fn webp(chunks: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut content = b"WEBP".to_vec();
+        for (kind, data) in chunks {
+            content.extend_from_slice(kind);
+            content.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            content.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                content.push(0);
+            }
+        }
+        let mut riff = b"RIFF".to_vec();
+        riff.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        riff.extend(content);
+        riff
+    }
+
+    #[test]
+    fn names_come_from_the_content() {
+        let hash = content_hash(&png(1, 1));
+        assert!(is_stored_name(&format!("{}.png", hash)));
+        assert_eq!(content_hash(&png(1, 1)), hash);
+        assert_ne!(content_hash(&png(2, 1)), hash);
+    }
+
+    #[test]
+    fn formats_are_sniffed() {
+        let settings = Settings::default();
+        let (image, extension) = validate(&png(3, 2), &settings).unwrap();
+        assert_eq!((image.dimensions(), extension), ((3, 2), "png"));
+        assert_eq!(validate(&encoded(3, 2, ImageOutputFormat::Jpeg(90)), &settings).unwrap().1, "jpg");
+        assert_eq!(validate(&encoded(3, 2, ImageOutputFormat::Gif), &settings).unwrap().1, "gif");
+
+        let rejected = |content: &[u8]| match validate(content, &settings) {
+            Err(OboeError::Upload(_)) => {},
+            _ => panic!("{:?} was accepted as an image", String::from_utf8_lossy(content)),
+        };
+        rejected(b"<?php system($_GET['c']); ?>");
+        rejected(b"RIFF\0\0\0\0WAVEfmt ");
+        // WebP, lossy, lossless and animated
+        rejected(&webp(&[(b"VP8 ", &[0x50, 0x01, 0x00, 0x9d, 0x01, 0x2a, 3, 0, 2, 0])]));
+        rejected(&webp(&[(b"VP8L", &[0x2f, 0x02, 0x40, 0x00, 0x00])]));
+        rejected(&webp(&[(b"VP8X", &[0x02, 0, 0, 0, 2, 0, 0, 1, 0, 0]), (b"ANIM", &[0; 6]),
+                         (b"ANMF", &[0, 0, 0, 0, 0, 0, 2, 0, 0, 1, 0, 0, 100, 0, 0, 0])]));
+        // the right magic bytes, but nothing after them
+        let mut truncated = png(30, 30);
+        truncated.truncate(40);
+        rejected(&truncated);
+    }
+
+    #[test]
+    fn limits() {
+        let settings = Settings { max_upload_bytes: 64, ..Settings::default() };
+        match validate(&png(200, 200), &settings) {
+            Err(OboeError::TooLarge(_)) => {},
+            _ => panic!("an image over the size limit was accepted"),
+        }
+        let settings = Settings { max_image_width: 100, max_image_height: 100, ..Settings::default() };
+        assert!(validate(&png(100, 100), &settings).is_ok());
+        assert!(validate(&png(101, 1), &settings).is_err());
+        assert!(validate(&png(1, 101), &settings).is_err());
+        let settings = Settings { max_image_pixels: 1000, ..Settings::default() };
+        assert!(validate(&png(50, 20), &settings).is_ok());
+        assert!(validate(&png(50, 21), &settings).is_err());
+    }
+
+    #[test]
+    fn only_stored_names_are_served() {
+        let hash = "a".repeat(64);
+        assert!(is_stored_name(&format!("{}.jpg", hash)));
+        assert!(is_stored_name(&format!("{}.webp", hash)));
+        assert!(is_stored_name(&format!("{}.thumb.gif", hash)));
+        assert!(!is_stored_name("image.png"));
+        assert!(!is_stored_name(&format!("{}.html", hash)));
+        assert!(!is_stored_name(&format!("../{}.png", hash)));
+    }
+
+    // the same image saved for two posts at once, where the first can't be added
+    #[test]
+    fn saved_and_abandoned() {
+        let directory = env::temp_dir().join(format!("oboe-uploads-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let store = LocalStore::new(&directory.to_string_lossy());
+        let temporary = directory.join("upload");
+        fs::write(&temporary, png(3, 2)).unwrap();
+        let settings = Settings::default();
+        let storage = MemoryStorage::new(&settings);
+
+        let media = save(prepare(&temporary, "a.png".to_owned(), false, &settings).unwrap(), &storage, &store).unwrap();
+        assert_eq!((media.width, media.height, media.original_name.as_str()), (3, 2, "a.png"));
+        assert!(store.exists(name_of(&media.thumbnail)).unwrap());
+        // the same image uploaded again uses the files that are there
+        let again = save(prepare(&temporary, "b.png".to_owned(), false, &settings).unwrap(), &storage, &store).unwrap();
+        assert_eq!(again.path, media.path);
+
+        // the files are kept for the other post, until that one can't be added either
+        abandon(slice::from_ref(&media), &storage, &store).unwrap();
+        assert!(store.exists(name_of(&media.path)).unwrap());
+        abandon(slice::from_ref(&again), &storage, &store).unwrap();
+        assert!(!store.exists(name_of(&media.path)).unwrap());
+        assert!(!store.exists(name_of(&media.thumbnail)).unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn names_in_a_media_store() {
+        assert_eq!(name_of("Pictures/cat.png"), "cat.png");
+        assert_eq!(name_of("Pictures/cat.thumb.png"), "cat.thumb.png");
+        assert_eq!(name_of("cat.png"), "cat.png");
+    }
+
+    #[test]
+    fn display_names() {
+        assert_eq!(display_name("cat.png"), "cat.png");
+        assert_eq!(display_name("C:\\Users\\me\\cat.png"), "cat.png");
+        assert_eq!(display_name("../../cat.png"), "cat.png");
+    }
+}
@@ -1,239 +1,524 @@
-use crate::postgres::{OP, Thread, Post};
-use crate::metainfo;
+use askama::Template;
+use rocket::http::Status;
 
-// These functions take a reference of a string and add HTML5-compliant tags around them.
+use crate::error::Result;
+use crate::html::percent_encode;
+use crate::media::MediaUrls;
+use crate::postgres::{Backlinks, Board, Media, OP, SearchHit, Thread, Post};
+use crate::storage::Page;
 
-pub fn to_xml_paragraph(string: &String) -> String {
-    format!("<p>{}</p>", string)
+// Pages are rendered from the templates in 'templates/', which are checked when compiling. They all
+// extend 'layout.html', holding the header, navigation bar and footer. Everything put into a template
+// is escaped, unless it is marked with '|safe'.
+
+// creates a clickable link to a thread from its board and thread id
+pub fn clickable_thread(ops: &OP) -> String {
+    format!("/{}/thread/{}", ops.board, ops.threadid)
 }
 
-pub fn to_xml_header1(string: &String) -> String {
-    format!("<h1>{}</h1>", string)
+// the index of a board
+pub fn clickable_board(board: &Board) -> String {
+    format!("/{}/", board.slug)
 }
 
-pub fn to_xml_header3(string: &String) -> String {
-    format!("<h3>{}</h3>", string)
+// the heading of a board as used in titles, e.g. '/b/ - Random'
+pub fn board_heading(board: &Board) -> String {
+    format!("/{}/ - {}", board.slug, board.title)
 }
 
-pub fn to_xml_link(url: &String, text: &String) -> String {
-    format!("<a class=\"underline--hover\" href=\"{}\">{}</a>", url, text)
+// a search result links straight to the matching post, the opening post is 'p0'
+pub fn clickable_hit(hit: &SearchHit) -> String {
+    format!("/{}/thread/{}#p{}", hit.board, hit.threadid, hit.postid)
 }
 
-// creates a clickable link to a thread from its thread id
-pub fn clickable_thread(ops: &OP) -> String {
-    format!("/thread/{}",ops.threadid)
+// what is shown in place of the thumbnail of a spoiler until it is clicked
+pub const SPOILER_PLACEHOLDER: &str = "/static/spoiler.svg";
+
+// the thumbnail an attached image is shown with, the placeholder for spoilers
+pub fn thumbnail_url(media: &Media, urls: MediaUrls) -> String {
+    if media.spoiler {
+        SPOILER_PLACEHOLDER.to_owned()
+    } else {
+        urls.of(&media.thumbnail)
+    }
 }
 
-// content is what should be in the div, class are the css classes applied
-pub fn to_xml_div_w_class(content: &String, class: &str) -> String {
-    format!("<div class=\"{}\">{}</div>", class, content)
+// the filters the templates use, e.g. '{{ op|thread_url }}'
+mod filters {
+    use crate::html::Markup;
+    use crate::media::MediaUrls;
+    use crate::postgres::{Board, Media, OP, SearchHit};
+    use crate::storage::{HIGHLIGHT_START, HIGHLIGHT_END};
+
+    pub fn thread_url(op: &OP) -> askama::Result<String> {
+        Ok(super::clickable_thread(op))
+    }
+
+    pub fn board_url(board: &Board) -> askama::Result<String> {
+        Ok(super::clickable_board(board))
+    }
+
+    pub fn heading(board: &Board) -> askama::Result<String> {
+        Ok(super::board_heading(board))
+    }
+
+    pub fn hit_url(hit: &SearchHit) -> askama::Result<String> {
+        Ok(super::clickable_hit(hit))
+    }
+
+    // where an image or thumbnail stored at 'path' is loaded from, e.g. '{{ media.path|media_url(urls) }}'
+    pub fn media_url(path: &str, urls: &MediaUrls) -> askama::Result<String> {
+        Ok(urls.of(path))
+    }
+
+    pub fn thumbnail_url(media: &Media, urls: &MediaUrls) -> askama::Result<String> {
+        Ok(super::thumbnail_url(media, *urls))
+    }
+
+    // what is known about an attached image, e.g. 'cat.png, 800x600, 112 KiB'
+    pub fn file_info(media: &Media) -> askama::Result<String> {
+        let mut info = Vec::new();
+        if !media.original_name.is_empty() {
+            info.push(media.original_name.clone());
+        }
+        // not recorded for images uploaded before media was
+        if media.width > 0 && media.height > 0 {
+            info.push(format!("{}x{}", media.width, media.height));
+        }
+        if media.byte_size > 0 {
+            info.push(format!("{} KiB", (media.byte_size + 1023) / 1024));
+        }
+        Ok(info.join(", "))
+    }
+
+    // the body of a post of the thread of 'op', see markup.rs
+    pub fn markup(body: &str, op: &OP) -> askama::Result<Markup> {
+        Ok(crate::markup::render(body, &super::clickable_thread(op)))
+    }
+
+    // a snippet of a 'SearchHit' with the matched words in <mark> tags. The snippet is escaped first,
+    // which leaves the highlight markers alone.
+    pub fn snippet(snippet: &str) -> askama::Result<Markup> {
+        let marked = Markup::text(snippet).into_string()
+            .replace(HIGHLIGHT_START, "<mark>")
+            .replace(HIGHLIGHT_END, "</mark>");
+        Ok(Markup::raw(marked))
+    }
 }
 
-pub fn to_xml_div_w_id(content: &String, id: &str) -> String {
-    format!("<div id=\"{}\">{}</div>", id, content)
+// What 'layout.html' needs from every page: its title, the boards to list in the navigation bar
+// and, on the pages of a board, that board.
+struct Layout<'a> {
+    title: String,
+    boards: &'a [Board],
+    current: Option<&'a Board>
 }
 
-pub fn to_xml_div_noclass_no_id(content: &String) -> String {
-    format!("<div>{}</div>", content)
+impl<'a> Layout<'a> {
+    fn is_current(&self, board: &Board) -> bool {
+        self.current.map(|current| current.slug == board.slug).unwrap_or(false)
+    }
 }
 
-// content is what should be in the body, class are the css classes applied
-pub fn to_xml_body(content: &String, class: &str) -> String {
-    format!("<body class=\"{}\">{}</body>", class, content)
+// links to the pages of a paginated view, 'url' is the view without the page. Only the pages
+// around the current one are linked, together with the first and the last.
+struct PageNavigation {
+    url: String,
+    page: u32,
+    pages: u32
 }
 
-// create <img> tags. 'handle' refers to where the images are mounted.
-pub fn to_xml_image(content: &String, handle: &'static str) -> String {
-    format!("<img src=\"{}{}\" alt=\"image not found\" class=\"imgThread\">", handle, content)
+// a link to the page 'number', or the pages left out between two links if 'gap' is set
+struct PageLink {
+    number: u32,
+    current: bool,
+    gap: bool
 }
 
-pub fn to_xml_image_gallery(content: &String, handle: &'static str) -> String {
-    format!("<img src=\"{}{}\" alt=\"image not found\" class=\"imgGallery\">", handle, content)
+// pages linked on either side of the current one
+const PAGE_WINDOW: u32 = 2;
+
+impl PageNavigation {
+    fn new<T>(url: String, page: &Page<T>) -> PageNavigation {
+        PageNavigation { url, page: page.page, pages: page.pages }
+    }
+
+    fn links(&self) -> Vec<PageLink> {
+        if self.pages == 0 {
+            return Vec::new();
+        }
+        let mut numbers = vec![1, self.pages];
+        numbers.extend(self.page.saturating_sub(PAGE_WINDOW).max(1)..=(self.page + PAGE_WINDOW).min(self.pages));
+        numbers.sort();
+        numbers.dedup();
+
+        let mut links = Vec::new();
+        let mut previous = 0;
+        for number in numbers {
+            if number > previous + 1 {
+                links.push(PageLink { number: 0, current: false, gap: true });
+            }
+            links.push(PageLink { number, current: number == self.page, gap: false });
+            previous = number;
+        }
+        links
+    }
+
+    fn separator(&self) -> &'static str {
+        if self.url.contains('?') { "&" } else { "?" }
+    }
 }
 
-// The header is always the same, with the exception of the title, which depends on the page currently viewed.
-pub fn retrieve_header(title: &str) -> String {
-    format!("<head><title>{}</title>
-	<meta charset=\"utf-8\">
-	<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
-	<link rel=\"stylesheet\" type=\"text/css\" href=\"/static/stylesheet.css\">
-	</head>", title)
+#[derive(Template)]
+#[template(path = "boards.html")]
+struct BoardsPage<'a> {
+    layout: Layout<'a>
 }
 
-// The navigation bar is a hardcoded string since it is always the same.
-pub fn retrieve_navigation_bar() -> String {
-    "<div class=\"background-white\"><div class=\"bar\"><nav class=\"nav\"><span class=\"nav_span\"><a href=\"/\"><img id=\"logo\" class=\"logo\" width=\"32\" height=\"32\" src=\"/static/oboe.png\" alt=\"logo\"></a><a class=\"nav_entity underline--hover bold blue\" href=\"/\">Home</a><span class=\"nav_separator\">/</span><a class=\"nav_entity underline--hover\" href=\"/gallery\">Gallery</a><span class=\"nav_separator\">/</span><a class=\"nav_entity underline--hover\" href=\"/makethread\">New Thread</a></span></nav></div></div>".to_owned()
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexPage<'a> {
+    layout: Layout<'a>,
+    board: &'a Board,
+    openings: Vec<OP>,
+    navigation: PageNavigation,
+    urls: MediaUrls<'a>
 }
 
-// imports the script to fetch new comments.
-pub fn retrieve_js_import() -> &'static str {
-    "<script src=\"/static/main.js\"></script>"
+#[derive(Template)]
+#[template(path = "gallery.html")]
+struct GalleryPage<'a> {
+    layout: Layout<'a>,
+    board: &'a Board,
+    images: Vec<Media>,
+    navigation: PageNavigation,
+    urls: MediaUrls<'a>
 }
 
-// a commented line with the last 'postid'. This is used by the script, else it would have to parse
-// the whole html to find out what the last post is.
-pub fn retrieve_post_counter(threadid: &i32) -> String {
-    format!("<!--{}-->", metainfo::get_postid(threadid, false).unwrap())
+#[derive(Template)]
+#[template(path = "thread.html")]
+struct ThreadPage<'a> {
+    layout: Layout<'a>,
+    op: OP,
+    posts: Vec<Post>,
+    // comments left out of a shortened view
+    omitted: i32,
+    // whether to link the shortened view of the last 'last_replies' comments
+    show_last: bool,
+    last_replies: u32,
+    // read by main.js to know which comments to fetch
+    last_postid: i32,
+    backlinks: Backlinks,
+    // images a reply can have, the form has no file field if it is 0
+    max_attachments: i32,
+    urls: MediaUrls<'a>
 }
 
-// Takes an array of 'OP', builds a full HTML page from it. This makes up the index/titlepage.
-pub fn xmlify_for_index(openings: Vec<OP>) -> String {
-    let mut thread_content = String::new();
+#[derive(Template)]
+#[template(path = "archive.html")]
+struct ArchivePage<'a> {
+    layout: Layout<'a>,
+    board: &'a Board,
+    openings: Vec<OP>,
+    navigation: PageNavigation
+}
 
-    // creation of "tiles" which contain user content
-    for op in openings.iter() {
-        let title = to_xml_link(&clickable_thread(op), &op.title);
-        let title = to_xml_div_w_class(&title, "title");
+#[derive(Template)]
+#[template(path = "makethread.html")]
+struct MakeThreadPage<'a> {
+    layout: Layout<'a>,
+    board: &'a Board,
+    // images a new thread can have
+    max_attachments: i32
+}
 
-        let poster = to_xml_div_w_class(&op.poster, "user");
-        let threadid = to_xml_div_w_class(&format!("ID: {}", &op.threadid), "id");
-        let time = to_xml_div_w_class(&op.time, "time");
-        let date = to_xml_div_w_class(&op.date, "date");
-        let thread_info = to_xml_div_noclass_no_id(&format!("{}{}{}{}", poster, threadid, time, date));
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchPage<'a> {
+    layout: Layout<'a>,
+    query: &'a str,
+    // false while only the form is shown
+    searched: bool,
+    hits: Vec<SearchHit>,
+    navigation: PageNavigation
+}
 
-        let image = to_xml_image(&op.img, "/");
-        let body = to_xml_paragraph(&op.body);
-        let content = to_xml_div_w_class(&format!("{}{}", image, body), "content");
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorPage<'a> {
+    layout: Layout<'a>,
+    heading: String,
+    message: &'a str
+}
 
-        let tile = to_xml_div_w_class(&format!("{}{}{}", title, thread_info, content), "tile");
+// Builds the titlepage, a tile for every board with its description.
+pub fn xmlify_for_boards(boards: &[Board]) -> Result<String> {
+    let layout = Layout { title: "Home".to_owned(), boards, current: None };
+    Ok(BoardsPage { layout }.render()?)
+}
 
-        thread_content.push_str(tile.as_str());
-    }
+// Takes a page of 'OP' of 'board', builds a full HTML page from it. This makes up the index of a board.
+// Stickies are shown first. The storage already lists them first, so they are all on the first page.
+// Images are loaded from 'urls', as with every page showing them.
+pub fn xmlify_for_index<'a>(board: &'a Board, boards: &'a [Board], openings: Page<OP>, urls: MediaUrls<'a>) -> Result<String> {
+    let navigation = PageNavigation::new(clickable_board(board), &openings);
+    let mut openings = openings.items;
+    // stable, the rest stays in the order of the last bump
+    openings.sort_by_key(|op| !op.sticky);
+
+    let layout = Layout { title: board_heading(board), boards, current: Some(board) };
+    Ok(IndexPage { layout, board, openings, navigation, urls }.render()?)
+}
+
+// Builds the full page of the gallery view of 'board', out of a page of images of both 'OP' and
+// 'Post' since this focuses on portraying all images currently in use. Posts with several images
+// have an entry for each.
+pub fn xmlify_for_gallery<'a>(board: &'a Board, boards: &'a [Board], images: Page<Media>, urls: MediaUrls<'a>) -> Result<String> {
+    let navigation = PageNavigation::new(format!("/{}/gallery", board.slug), &images);
+    let layout = Layout { title: "Gallery".to_owned(), boards, current: Some(board) };
+    Ok(GalleryPage { layout, board, images: images.items, navigation, urls }.render()?)
+}
 
-    //wrap all tiles into one division, "main__"
-    thread_content = to_xml_div_w_class(&thread_content, "main__");
+// Builds a HTML page for a full thread, with a opening post ('OP') and all its comments ('Post').
+// If only the last comments were retrieved, a link to the full thread is shown above them. Threads
+// with more than 'last_replies' comments get a link to the shortened view instead. Every post lists
+// the ones quoting it, out of 'backlinks'. Replies can be made with up to 'max_attachments' images.
+pub fn xmlify_for_indvthread<'a>(board: &'a Board, boards: &'a [Board], thread: Thread, backlinks: Backlinks, last_replies: u32, max_attachments: i32, urls: MediaUrls<'a>) -> Result<String> {
+    let op = thread.op;
+    let (omitted, show_last) = match &thread.posts {
+        Some(posts) => {
+            let omitted = op.post_count - posts.len() as i32;
+            (omitted.max(0), omitted <= 0 && op.post_count > last_replies as i32)
+        },
+        None => (0, false),
+    };
+    let posts = thread.posts.unwrap_or_default();
+    let last_postid = posts.iter().map(|post| post.postid).max().unwrap_or(0);
 
-    format!("
-        <!DOCTYPE html><html lang=\"en\" xml:lang=\"en\">{}<body class=\"background keep_space\">{}{}</body></html>", retrieve_header("Home"), retrieve_navigation_bar(), thread_content
-    )
+    let layout = Layout { title: op.title.clone(), boards, current: Some(board) };
+    Ok(ThreadPage { layout, op, posts, omitted, show_last, last_replies, last_postid, backlinks, max_attachments, urls }.render()?)
 }
 
-// Builds the full page of the gallery view of the site. Needs both all 'OP' and all 'Post' since
-// this focuses on portraying all images currently in use.
-pub fn xmlify_for_gallery(from_openings: Vec<OP>, from_posts: Vec<Post>) -> String {
-    let mut images = Vec::new();
-    for op in from_openings.iter() {
-        images.push(&op.img);
+// Builds the list of archived threads of 'board', the most recently archived first.
+pub fn xmlify_for_archive(board: &Board, boards: &[Board], openings: Page<OP>) -> Result<String> {
+    let navigation = PageNavigation::new(format!("/{}/archive", board.slug), &openings);
+    let layout = Layout { title: "Archive".to_owned(), boards, current: Some(board) };
+    Ok(ArchivePage { layout, board, openings: openings.items, navigation }.render()?)
+}
+
+// Builds the form to create a thread on 'board' with up to 'max_attachments' images.
+pub fn xmlify_for_makethread(board: &Board, boards: &[Board], max_attachments: i32) -> Result<String> {
+    let layout = Layout { title: "New Thread".to_owned(), boards, current: Some(board) };
+    Ok(MakeThreadPage { layout, board, max_attachments }.render()?)
+}
+
+// Builds the search page. Without a 'query' only the search form is shown, otherwise a page of
+// 'results' follows, each linking to the matching post.
+pub fn xmlify_for_search(query: &str, results: Option<Page<SearchHit>>, boards: &[Board]) -> Result<String> {
+    let url = format!("/search?q={}", percent_encode(query));
+    let (searched, hits, navigation) = match results {
+        Some(results) => {
+            let navigation = PageNavigation::new(url, &results);
+            (true, results.items, navigation)
+        },
+        None => (false, Vec::new(), PageNavigation { url, page: 1, pages: 0 }),
+    };
+    let layout = Layout { title: "Search".to_owned(), boards, current: None };
+    Ok(SearchPage { layout, query, searched, hits, navigation }.render()?)
+}
+
+// Builds the page shown when a request fails, with the status code as heading.
+pub fn xmlify_for_error(status: Status, message: &str) -> Result<String> {
+    let layout = Layout { title: status.reason.to_owned(), boards: &[], current: None };
+    Ok(ErrorPage { layout, heading: status.code.to_string(), message }.render()?)
+}
+
+// the page linked when a submitted form is incomplete
+pub fn xmlify_for_invalid_form() -> Result<String> {
+    let layout = Layout { title: "Invalid Form".to_owned(), boards: &[], current: None };
+    let message = "All fields except the identifier have to be filled in. You can go back and re-submit, no data you entered was saved.";
+    Ok(ErrorPage { layout, heading: "Invalid Form Submitted".to_owned(), message }.render()?)
+}
+
+// every page built from user content is fed with markup where plain text belongs
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::config::Settings;
+    use crate::media::MediaUrls;
+    use crate::postgres::{Backlinks, Board, Media, OP, Post, Thread};
+    use crate::storage::Page;
+    use crate::xmlify::{PageNavigation, xmlify_for_gallery, xmlify_for_index, xmlify_for_indvthread};
+
+    const PAYLOAD: &str = "<script>alert(1)</script>";
+    const ATTRIBUTE_PAYLOAD: &str = "x\" onerror=\"alert(1)";
+
+    fn board() -> Board {
+        Board {
+            slug: "b".to_owned(),
+            title: PAYLOAD.to_owned(),
+            description: PAYLOAD.to_owned(),
+            max_threads: None,
+            bump_limit: None,
+            nsfw: false,
+            keep_metadata: false,
+            max_thread_attachments: None,
+            max_reply_attachments: None
+        }
+    }
+
+    fn hostile_media() -> Media {
+        Media {
+            id: 1,
+            hash: String::new(),
+            mime: "image/png".to_owned(),
+            width: 800,
+            height: 600,
+            byte_size: 2048,
+            original_name: PAYLOAD.to_owned(),
+            path: ATTRIBUTE_PAYLOAD.to_owned(),
+            thumbnail: ATTRIBUTE_PAYLOAD.to_owned(),
+            spoiler: false
+        }
+    }
+
+    fn hostile_thread() -> OP {
+        OP {
+            threadid: 1,
+            board: "b".to_owned(),
+            poster: PAYLOAD.to_owned(),
+            title: PAYLOAD.to_owned(),
+            body: PAYLOAD.to_owned(),
+            attachments: vec![hostile_media()],
+            created_at: Utc::now(),
+            last_bump: Utc::now(),
+            post_count: 1,
+            archived_at: None,
+            sticky: false,
+            locked: false
+        }
     }
-    for post in from_posts.iter() {
-        images.push(&post.img);
+
+    fn hostile_post() -> Post {
+        Post {
+            threadid: 1,
+            poster: PAYLOAD.to_owned(),
+            body: format!("</p>{}", PAYLOAD),
+            attachments: vec![hostile_media()],
+            created_at: Utc::now(),
+            postid: 1,
+            sage: false
+        }
     }
 
-    // xmlified images
-    let mut content = String::new();
-    for image in images.iter() {
-        content.push_str(to_xml_image_gallery(*image, "/").as_str());
+    // no payload made it through as markup
+    fn assert_inert(page: &str) {
+        assert!(!page.contains(PAYLOAD), "unescaped script in {}", page);
+        assert!(!page.contains("\" onerror=\""), "unescaped attribute in {}", page);
+        assert!(!page.contains("</p><script>"));
     }
 
-    let heading = to_xml_div_w_class(&"Image Gallery".to_owned(), "title");
-    let spacer = to_xml_header3(&"".to_owned());
+    #[test]
+    fn index_escapes_user_content() {
+        let page = xmlify_for_index(&board(), &[board()], Page { items: vec![hostile_thread()], page: 1, pages: 1 }, MediaUrls::default()).unwrap();
+        assert_inert(&page);
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    }
 
-    let gallery = to_xml_div_w_class(&format!("{}{}{}", heading, spacer, content), "gallery");
-    let gallery_in_tile = to_xml_div_w_class(&gallery, "main__");
-    format!("
-        <!DOCTYPE html><html lang=\"en\" xml:lang=\"en\">
-        {}
-        <body class=\"background keep_space\">
-        {}
-        {}
-        </body>
-        </html>", retrieve_header("Gallery"), retrieve_navigation_bar(), gallery_in_tile
-    )
-}
+    #[test]
+    fn gallery_escapes_image_paths() {
+        let page = xmlify_for_gallery(&board(), &[board()], Page { items: vec![hostile_media()], page: 1, pages: 1 }, MediaUrls::default()).unwrap();
+        assert_inert(&page);
+        assert!(page.contains("src=\"/x&quot; onerror=&quot;alert(1)\""));
+    }
 
-// Builds a HTML page for a full thread, with a opening post ('OP') and all its comments ('Post').
-pub fn xmlify_for_indvthread(thread: Thread) -> String {
-
-    let opening = &thread.op;
-    let posts = thread.posts;
-    // the opening is on a single tile
-    let title = to_xml_link(&clickable_thread(&opening), &opening.title);
-    let title = to_xml_div_w_class(&title, "title");
-
-    let poster = to_xml_div_w_class(&opening.poster, "user");
-    let threadid = to_xml_div_w_class(&format!("ID: {}", &opening.threadid), "id");
-    let time = to_xml_div_w_class(&opening.time, "time");
-    let date = to_xml_div_w_class(&opening.date, "date");
-    let thread_info = to_xml_div_noclass_no_id(&format!("{}{}{}{}", poster, threadid, time, date));
-
-    let image = to_xml_image(&opening.img, "/");
-    let body = to_xml_paragraph(&opening.body);
-    let content = to_xml_div_w_class(&format!("{}{}", image, body), "content");
-
-    let opening_tile = to_xml_div_w_class(&format!("{}{}{}", title, thread_info, content), "tile");
-
-    // all comments are put on a single tile, if there are any
-    match posts {
-        // if no comments are present, only the reply field make_post is shown
-        None => {
-            //empty division for comments added, so that AJAX may fill up with future comments
-            let make_post = {
-                to_xml_div_w_class(
-                    &"<div class=\"title\">Comments</div><div id=\"commentSection\"></div><div class=\"comment\"><div class=\"title\">Make a Comment</div><form enctype=\"multipart/form-data\" method=\"post\" autocomplete=\"off\"><div class=\"form_space\"><input type=\"text\" name=\"poster\" placeholder=\"Identifier\"></div><textarea class=\"textarea--v\" name=\"body\" rows=\"5\" cols=\"50\" placeholder=\"Thread Content\"></textarea><div class=\"form_space\"><input type=\"file\" name=\"image\" accept=\"image/*\"></div><div class=\"button_space\"><input type=\"submit\" class=\"button button--blue\" value=\"make comment\"></div></form></div>".to_owned(),
-                    "tile"
-                )
-            };
-            let wrapper = to_xml_div_w_class(&format!("{}{}", opening_tile, make_post), "main__");
-            return format!("
-            <!DOCTYPE html><html lang=\"en\" xml:lang=\"en\">
-            {}
-            <body class=\"background keep_space\">
-            {}
-            {}
-            {}
-            {}
-            </body>
-            </html>", retrieve_header(&opening.title), retrieve_post_counter(&opening.threadid), retrieve_navigation_bar(), wrapper, retrieve_js_import()
-            );
-        },
-        // else all comments are shown, plus the reply field
-        Some(posts) => {
+    #[test]
+    fn thread_escapes_user_content() {
+        let thread = Thread { op: hostile_thread(), posts: Some(vec![hostile_post()]) };
+        let page = xmlify_for_indvthread(&board(), &[board()], thread, Backlinks::default(), 50, 4, MediaUrls::default()).unwrap();
+        assert_inert(&page);
+        // the title of the page as well
+        assert!(page.contains("<title>&lt;script&gt;"));
+        // main.js reads the last post from the comment right at the start of the body
+        assert!(page.contains("<body class=\"background keep_space\">\n<!--1-->"));
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;, 800x600, 2 KiB"));
+    }
 
-            // everything wrapped in the second division class "tile" that will contain all comments and the POST-form
-            let mut comments_tile = String::from("<div class=\"title\">Comments</div>");
-            // only the comments
-            let mut comment_section = String::new();
+    #[test]
+    fn posts_without_images() {
+        let op = OP { attachments: Vec::new(), ..hostile_thread() };
+        let post = Post { attachments: Vec::new(), ..hostile_post() };
+        let page = xmlify_for_indvthread(&board(), &[board()], Thread { op, posts: Some(vec![post]) }, Backlinks::default(), 50, 0, MediaUrls::default()).unwrap();
+        assert!(!page.contains("imgThread"));
+        assert!(!page.contains("class=\"filename\""));
+        // nor can replies have one
+        assert!(!page.contains("type=\"file\""));
+    }
 
-            for post in posts.iter() {
-                let poster = to_xml_div_w_class(&post.poster, "user");
-                let postid = to_xml_div_w_class(&format!("ID: {}", &post.postid), "id");
-                let time = to_xml_div_w_class(&post.time, "time");
-                let date = to_xml_div_w_class(&post.date, "date");
-                let thread_info = to_xml_div_noclass_no_id(&format!("{}{}{}{}", poster, postid, time, date));
+    #[test]
+    fn several_images_are_a_grid() {
+        let op = OP { attachments: vec![hostile_media(), Media { original_name: "second.png".to_owned(), ..hostile_media() }], ..hostile_thread() };
+        let page = xmlify_for_indvthread(&board(), &[board()], Thread { op, posts: Some(vec![hostile_post()]) }, Backlinks::default(), 50, 4, MediaUrls::default()).unwrap();
+        assert_inert(&page);
+        assert_eq!(page.matches("class=\"attachments grid\"").count(), 1);
+        assert_eq!(page.matches("class=\"attachments\"").count(), 1);
+        assert!(page.contains("second.png, 800x600, 2 KiB"));
+        // a file input with its own spoiler checkbox for each of them
+        assert_eq!(page.matches("type=\"file\"").count(), 4);
+        assert_eq!(page.matches("name=\"spoiler\"").count(), 4);
+    }
+
+    #[test]
+    fn spoilers_are_hidden() {
+        let spoiler = Media { spoiler: true, thumbnail: "Pictures/secret.thumb.png".to_owned(), ..hostile_media() };
+        let op = OP { attachments: vec![spoiler.clone(), hostile_media()], ..hostile_thread() };
+        let page = xmlify_for_indvthread(&board(), &[board()], Thread { op: op.clone(), posts: None }, Backlinks::default(), 50, 4, MediaUrls::default()).unwrap();
+        assert_inert(&page);
+        assert!(page.contains("<img src=\"/static/spoiler.svg\""));
+        assert!(!page.contains("src=\"/Pictures/secret.thumb.png\""));
+        // only the other image is shown right away
+        assert_eq!(page.matches("src=\"/x&quot; onerror=&quot;alert(1)\"").count(), 1);
+
+        let index = xmlify_for_index(&board(), &[board()], Page { items: vec![op], page: 1, pages: 1 }, MediaUrls::default()).unwrap();
+        assert!(index.contains("<img src=\"/static/spoiler.svg\""));
+        let gallery = xmlify_for_gallery(&board(), &[board()], Page { items: vec![spoiler], page: 1, pages: 1 }, MediaUrls::default()).unwrap();
+        assert!(gallery.contains("<img src=\"/static/spoiler.svg\""));
+        assert!(!gallery.contains("src=\"/Pictures/secret.thumb.png\""));
+    }
 
-                let image = to_xml_image(&post.img, "/");
-                let body = to_xml_paragraph(&post.body);
-                let content = to_xml_div_w_class(&format!("{}{}", image, body), "content");
+    #[test]
+    fn images_from_a_cdn() {
+        let settings = Settings { media_url: Some("https://cdn.example.com".to_owned()), ..Settings::default() };
+        let image = Media { path: "Pictures/cat.png".to_owned(), thumbnail: "Pictures/cat.thumb.png".to_owned(), ..hostile_media() };
+        let spoiler = Media { spoiler: true, ..image.clone() };
+        let op = OP { attachments: vec![image.clone(), spoiler], ..hostile_thread() };
+        let page = xmlify_for_indvthread(&board(), &[board()], Thread { op, posts: None }, Backlinks::default(), 50, 4, MediaUrls::new(&settings)).unwrap();
+        assert_eq!(page.matches("<a href=\"https://cdn.example.com/cat.png\">").count(), 2);
+        assert!(page.contains("<img src=\"https://cdn.example.com/cat.thumb.png\""));
+        assert!(page.contains("data-thumbnail=\"https://cdn.example.com/cat.thumb.png\""));
+        assert!(!page.contains("\"/Pictures/"));
+
+        let gallery = xmlify_for_gallery(&board(), &[board()], Page { items: vec![image], page: 1, pages: 1 }, MediaUrls::new(&settings)).unwrap();
+        assert!(gallery.contains("<a href=\"https://cdn.example.com/cat.png\">"));
+    }
 
-                let single_comment = to_xml_div_w_class(&format!("{}{}", thread_info, content), "comment");
+    // the numbers shown in the navigation of 'page' out of 'pages', 0 for a gap
+    fn page_links(page: u32, pages: u32) -> Vec<u32> {
+        PageNavigation { url: "/b/".to_owned(), page, pages }.links().iter()
+            .map(|link| if link.gap { 0 } else { link.number })
+            .collect()
+    }
 
-                &comment_section.push_str(single_comment.as_str());
-            }
-            // wrap final list of xml-compliant comments in div "commentSection" that is used by AJAX
-            // to parse and add new comments
-            comment_section = to_xml_div_w_id(&comment_section, "commentSection");
-
-            comments_tile.push_str(&comment_section.as_str());
-            // at the bottom, users can make posts. This is the form to make these.
-            let make_post = {
-                "<div class=\"comment\"><div class=\"title\">Make a Comment</div><form enctype=\"multipart/form-data\" method=\"post\" autocomplete=\"off\"><div class=\"form_space\"><input type=\"text\" name=\"poster\" placeholder=\"Identifier\"></div><textarea class=\"textarea--v\" name=\"body\" rows=\"5\" cols=\"50\" placeholder=\"Thread Content\"></textarea><div class=\"form_space\"><input type=\"file\" name=\"image\" accept=\"image/*\"></div><div class=\"button_space\"><input type=\"submit\" class=\"button button--blue\" value=\"make comment\"></div></form></div>"
-            };
-            &comments_tile.push_str(make_post);
-            let comments_tile = to_xml_div_w_class(&comments_tile, "tile");
-
-            let wrapper = to_xml_div_w_class(&format!("{}{}", opening_tile, comments_tile), "main__");
-
-            return format!("
-            <!DOCTYPE html><html lang=\"en\" xml:lang=\"en\">
-            {}
-            <body class=\"background keep_space\">
-            {}
-            {}
-            {}
-            {}
-            </body>
-            </html>", retrieve_header(&opening.title), retrieve_post_counter(&opening.threadid), retrieve_navigation_bar(), wrapper, retrieve_js_import()
-            );
-        }
+    #[test]
+    fn page_links_are_windowed() {
+        assert_eq!(page_links(1, 0), Vec::<u32>::new());
+        assert_eq!(page_links(1, 1), vec![1]);
+        assert_eq!(page_links(2, 5), vec![1, 2, 3, 4, 5]);
+        assert_eq!(page_links(1, 100), vec![1, 2, 3, 0, 100]);
+        assert_eq!(page_links(50, 100), vec![1, 0, 48, 49, 50, 51, 52, 0, 100]);
+        assert_eq!(page_links(100, 100), vec![1, 0, 98, 99, 100]);
+        // a single page left out is still a gap
+        assert_eq!(page_links(5, 10), vec![1, 0, 3, 4, 5, 6, 7, 0, 10]);
     }
-}
\ No newline at end of file
+}
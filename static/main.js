@@ -2,12 +2,30 @@
 //in a thread every 5secs
 
 let lastComment = getLastComment();
+localizeTimes(document);
+document.addEventListener("click", revealSpoiler);
 connect();
 
+// the server renders times in UTC, this rewrites every <time> tag in the timezone of the reader
+function localizeTimes(root) {
+
+    let times = root.querySelectorAll("time[datetime]");
+    for(let i = 0; i < times.length; i++) {
+        let moment = new Date(times[i].getAttribute("datetime"));
+        if(times[i].dataset.format === "%H:%M:%S") {
+            times[i].textContent = moment.toLocaleTimeString();
+        }
+        else {
+            times[i].textContent = moment.toLocaleDateString();
+        }
+    }
+}
+
 function connect() {
 
     let request = new XMLHttpRequest();
-    request.open('GET', window.location.href + "/" + lastComment);
+    request.open('GET', window.location.pathname + "/" + lastComment);
+    request.setRequestHeader('Accept', 'application/json');
 
     request.onload = function () {
         if(request.status >= 200 && request.status < 400) {
@@ -43,13 +61,27 @@ function findComment(comments) {
 
         let user = comments[i].poster;
         let postId = comments[i].postid;
-        let time = comments[i].time;
-        let date = comments[i].date;
-        let content = comments[i].body;
-        let img = comments[i].img;
+        let createdAt = new Date(comments[i].created_at);
+        let time = createdAt.toLocaleTimeString();
+        let date = createdAt.toLocaleDateString();
+        let content = comments[i].body_html;
+        // empty for posts without an image
+        let attachments = comments[i].attachments;
+        let replies = comments[i].replies;
 
         if(lastComment < postId) {
-            createComment(user, postId, time, date, content, img);
+            createComment(user, postId, time, date, content, attachments);
+            for(let j = 0; j < replies.length; j++) {
+                addBacklink(postId, replies[j]);
+            }
+            // the posts quoted by the new one list it as a reply from now on
+            let quotes = document.getElementById("p" + postId).querySelectorAll(".body a.quotelink");
+            for(let j = 0; j < quotes.length; j++) {
+                let target = quotes[j].hash.match(/^#p(\d+)$/);
+                if(target && quotes[j].pathname === window.location.pathname) {
+                    addBacklink(target[1], postId);
+                }
+            }
             lastComment = postId;
         }
         //console.log(lastComment);
@@ -58,10 +90,11 @@ function findComment(comments) {
 
 //createComment("1", "2", "3", "4", "5", "6");
 
-function createComment(user, postId, time, date, content, img) {
+function createComment(user, postId, time, date, content, attachments) {
 
     let divComment = document.createElement("div");
     divComment.className = "comment";
+    divComment.id = "p" + postId;
     document.getElementById("commentSection").appendChild(divComment);
 
     let divInfo = document.createElement("div");
@@ -71,15 +104,15 @@ function createComment(user, postId, time, date, content, img) {
     divContent.className = "content";
     divComment.appendChild(divContent);
 
-    let image = document.createElement("img");
-    image.className = "imgThread";
-    image.alt = "image not found";
-    image.src = "/" + img;
 
-    let pContent = document.createElement("p");
+    // 'body_html' is rendered and escaped by the server, like the comments already on the page
+    let pContent = document.createElement("div");
+    pContent.className = "body";
     pContent.innerHTML = content;
 
-    divContent.appendChild(image);
+    if(attachments.length > 0) {
+        divContent.appendChild(createAttachments(attachments));
+    }
     divContent.appendChild(pContent);
 
     let divUser = document.createElement("div");
@@ -92,13 +125,99 @@ function createComment(user, postId, time, date, content, img) {
     divTime.className = "time";
     divDate.className = "date";
 
-    divUser.innerHTML = user;
-    divId.innerHTML = "ID: " + postId;
-    divTime.innerHTML = time;
-    divDate.innerHTML = date;
+    divUser.textContent = user;
+    divId.textContent = "ID: " + postId;
+    divTime.textContent = time;
+    divDate.textContent = date;
 
     divInfo.appendChild(divUser);
     divInfo.appendChild(divId);
     divInfo.appendChild(divTime);
     divInfo.appendChild(divDate);
+}
+
+// the images of a comment, like the server shows them. Each thumbnail links to the full image, both
+// loaded from where the server says, which may be a CDN.
+function createAttachments(attachments) {
+
+    let divAttachments = document.createElement("div");
+    divAttachments.className = attachments.length > 1 ? "attachments grid" : "attachments";
+    for(let i = 0; i < attachments.length; i++) {
+        let figure = document.createElement("figure");
+        figure.className = "attachment";
+        let link = document.createElement("a");
+        link.href = attachments[i].url;
+        let image = document.createElement("img");
+        image.className = "imgThread";
+        image.alt = "image not found";
+        if(attachments[i].spoiler) {
+            image.classList.add("spoilerImage");
+            image.dataset.thumbnail = attachments[i].thumbnail_url;
+            image.src = "/static/spoiler.svg";
+        }
+        else {
+            image.src = attachments[i].thumbnail_url;
+        }
+        link.appendChild(image);
+        figure.appendChild(link);
+        let caption = document.createElement("figcaption");
+        caption.className = "filename";
+        caption.textContent = fileInfo(attachments[i]);
+        figure.appendChild(caption);
+        divAttachments.appendChild(figure);
+    }
+    return divAttachments;
+}
+
+// spoilers show a placeholder until they are clicked. The first click shows their thumbnail instead
+// of following the link to the full image.
+function revealSpoiler(event) {
+
+    let image = event.target;
+    if(image.classList && image.classList.contains("spoilerImage")) {
+        event.preventDefault();
+        image.src = image.dataset.thumbnail;
+        image.classList.remove("spoilerImage");
+    }
+}
+
+// what is known about an attached image, like the server shows it next to the posts already on the page
+function fileInfo(media) {
+
+    let info = [];
+    if(media.original_name) {
+        info.push(media.original_name);
+    }
+    if(media.width > 0 && media.height > 0) {
+        info.push(media.width + "x" + media.height);
+    }
+    if(media.byte_size > 0) {
+        info.push(Math.ceil(media.byte_size / 1024) + " KiB");
+    }
+    return info.join(", ");
+}
+
+// lists 'reply' under the post 'postId', like the server does for the posts already on the page
+function addBacklink(postId, reply) {
+
+    let post = document.getElementById("p" + postId);
+    if(!post) {
+        return;
+    }
+    let backlinks = post.querySelector(".backlinks");
+    if(!backlinks) {
+        backlinks = document.createElement("div");
+        backlinks.className = "backlinks";
+        backlinks.textContent = "Replies:";
+        post.appendChild(backlinks);
+    }
+    if(backlinks.querySelector("a[href='#p" + reply + "']")) {
+        return;
+    }
+    let link = document.createElement("a");
+    link.className = "quotelink";
+    link.href = "#p" + reply;
+    link.textContent = ">>" + reply;
+    backlinks.appendChild(document.createTextNode(" "));
+    backlinks.appendChild(link);
 }
\ No newline at end of file